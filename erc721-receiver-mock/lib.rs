@@ -0,0 +1,94 @@
+// 標準ライブラリがなかったら標準ライブラリを使わない宣言
+#![cfg_attr(not(feature = "std"), no_std)]
+
+// テスト用の受け取り側コントラクト
+// acceptがfalseのときは受け取りを拒否する
+#[ink::contract]
+mod erc721_receiver_mock {
+    use erc721::internal::TokenId;
+    use erc721::receiver::{Erc721Receiver, ON_ERC721_RECEIVED_SELECTOR};
+    use ink::prelude::vec::Vec;
+
+    #[ink(storage)]
+    pub struct Erc721ReceiverMock {
+        // 受け取るかどうか
+        accept: bool,
+        // 受け取った回数
+        received: u32,
+        // 最後に受け取ったときのdata
+        last_data: Vec<u8>,
+    }
+
+    impl Erc721ReceiverMock {
+        #[ink(constructor)]
+        pub fn new(accept: bool) -> Self {
+            Self {
+                accept,
+                received: 0,
+                last_data: Vec::new(),
+            }
+        }
+
+        #[ink(message)]
+        pub fn set_accept(&mut self, accept: bool) {
+            self.accept = accept;
+        }
+
+        #[ink(message)]
+        pub fn received(&self) -> u32 {
+            self.received
+        }
+
+        #[ink(message)]
+        pub fn last_data(&self) -> Vec<u8> {
+            self.last_data.clone()
+        }
+    }
+
+    impl Erc721Receiver for Erc721ReceiverMock {
+        #[ink(message)]
+        fn on_erc721_received(
+            &mut self,
+            _operator: AccountId,
+            _from: AccountId,
+            _id: TokenId,
+            data: Vec<u8>,
+        ) -> [u8; 4] {
+            if !self.accept {
+                return [0; 4];
+            }
+            self.received += 1;
+            self.last_data = data;
+            ON_ERC721_RECEIVED_SELECTOR
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[ink::test]
+        fn accepts_and_records_data() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut mock = Erc721ReceiverMock::new(true);
+            assert_eq!(
+                mock.on_erc721_received(accounts.alice, accounts.alice, 1, vec![1, 2]),
+                ON_ERC721_RECEIVED_SELECTOR
+            );
+            assert_eq!(mock.received(), 1);
+            assert_eq!(mock.last_data(), vec![1, 2]);
+        }
+
+        #[ink::test]
+        fn rejects_when_disabled() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut mock = Erc721ReceiverMock::new(true);
+            mock.set_accept(false);
+            assert_ne!(
+                mock.on_erc721_received(accounts.alice, accounts.alice, 1, Vec::new()),
+                ON_ERC721_RECEIVED_SELECTOR
+            );
+            assert_eq!(mock.received(), 0);
+        }
+    }
+}
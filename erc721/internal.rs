@@ -0,0 +1,263 @@
+// ERC-721の中心部分(所有者・承認・所有数)
+// 他のink!コントラクトはDataをストレージに持ってErc721Internalを実装すれば
+// フックだけ上書きしてそのまま使える
+use ink::primitives::AccountId;
+use ink::storage::Mapping;
+use scale::{Decode, Encode};
+
+use crate::keys;
+
+pub type TokenId = u128; // TokenId
+
+// エラー定義
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone, Copy)] // いろいろtraitを実装
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum Error {
+    NotOwner,
+    NotApproved,
+    TokenExists,
+    TokenNotFound,
+    CannotInsert,
+    CannotFetchValue,
+    // ゼロアドレスに送ろうとした・Approveしようとした
+    ZeroAddressRecipient,
+    // 自分自身を全承認しようとした
+    SelfApproval,
+    // 所有者自身をApproveしようとした
+    ApproveToOwner,
+    // 所有者自身に送ろうとした
+    TransferToSelf,
+    InvalidParameter,
+    MaxSupplyReached,
+    InsufficientPayment,
+    TransferFailed,
+    BatchTooLarge,
+    InvariantViolation,
+    TooManyOperators,
+    InsufficientBalance,
+    NoteTooLong,
+    TooManyNotes,
+    MemoTooLong,
+    NotModerator,
+    // 受け取る必要のない送金がついていた
+    UnexpectedValue,
+    // 指定の期限を過ぎている
+    DeadlineExpired,
+    // 受け取り側コントラクトが受け取りを拒否した
+    ReceiverRejected,
+    // 所有数やTokenIdが上限を超える
+    Overflow,
+    // 所有数が0より小さくなる
+    Underflow,
+    // 属性のキーが空・長すぎる、または値が長すぎる
+    AttributeTooLong,
+    TooManyAttributes,
+    // CIDの長さや文字が正しくない
+    InvalidCid,
+    // チェーンエクステンションから乱数を取れなかった
+    RandomnessUnavailable,
+    // スクリプトのチャンクが大きすぎる
+    ScriptChunkTooLarge,
+    // すでにリビールされている
+    AlreadyRevealed,
+    // mintが始まったあとは変更できない
+    MintAlreadyStarted,
+    // トークンのメタデータが固定されている
+    MetadataFrozen,
+    // まだリビールされていない
+    NotRevealed,
+    // トークンの名前や説明が長すぎる
+    TextTooLong,
+    // アーティストとして登録されていない
+    NotArtist,
+    // 署名が空か長すぎる
+    InvalidSignature,
+    // シードの引き直しはトークンごとに一回だけ
+    AlreadyRerolled,
+    // そのエディションはすでにmintされている
+    EditionExists,
+}
+
+// ERC-721の中心となるストレージ
+#[ink::storage_item]
+#[derive(Default, Debug)]
+pub struct Data {
+    pub token_owner: Mapping<TokenId, AccountId, keys::TokenOwner>,
+    pub token_approvals: Mapping<TokenId, AccountId, keys::TokenApprovals>,
+    pub owned_tokens_count: Mapping<AccountId, u64, keys::OwnedTokensCount>,
+    pub operator_approvals: Mapping<(AccountId, AccountId), (), keys::OperatorApprovals>,
+}
+
+pub trait Erc721Internal {
+    fn data(&self) -> &Data;
+    fn data_mut(&mut self) -> &mut Data;
+
+    // 所有数が変わったあとに呼ばれるフック
+    fn after_balance_changed(&mut self, _of: &AccountId, _before: u64, _after: u64) {}
+
+    // トークンが追加・削除されたあとに呼ばれるフック
+    // まとめて追加したときは所有数を更新したあとに一回だけ呼ばれる
+    fn after_tokens_added(&mut self, _to: &AccountId, _ids: &[TokenId]) {}
+    fn after_tokens_removed(&mut self, _from: &AccountId, _ids: &[TokenId]) {}
+
+    // mint・burn・転送の前後に呼ばれるフック(mintはfromがNone、burnはtoがNone)
+    // beforeがエラーを返すとその操作は失敗する
+    fn before_token_transfer(
+        &mut self,
+        _from: Option<&AccountId>,
+        _to: Option<&AccountId>,
+        _id: TokenId,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+    fn after_token_transfer(
+        &mut self,
+        _from: Option<&AccountId>,
+        _to: Option<&AccountId>,
+        _id: TokenId,
+    ) {
+    }
+
+    fn balance_of_or_zero(&self, of: &AccountId) -> u64 {
+        self.data().owned_tokens_count.get(of).unwrap_or(0)
+    }
+
+    fn exists(&self, id: TokenId) -> bool {
+        self.data().token_owner.contains(id)
+    }
+
+    // トークンの所有者。トークンがなければエラー
+    fn owner_or_err(&self, id: TokenId) -> Result<AccountId, Error> {
+        self.data().token_owner.get(id).ok_or(Error::TokenNotFound)
+    }
+
+    fn approved_for_all(&self, owner: AccountId, operator: AccountId) -> bool {
+        self.data().operator_approvals.contains((&owner, &operator))
+    }
+
+    // 指定のアドレスが所有者　または　指定のトークンに対してのApprovalがある　または　allでApprovalされてる
+    // 所有者は呼び出し側で読んだものを渡す
+    fn approved_or_owner(
+        &self,
+        from: AccountId,
+        owner: AccountId,
+        id: TokenId,
+    ) -> Result<(), Error> {
+        // ゼロアドレス
+        if is_zero(&from) {
+            return Err(Error::NotApproved);
+        }
+
+        if from == owner
+            || Some(from) == self.data().token_approvals.get(id)
+            || self.approved_for_all(owner, from)
+        {
+            Ok(())
+        } else {
+            Err(Error::NotApproved)
+        }
+    }
+
+    fn clear_approval(&self, id: TokenId) {
+        self.data().token_approvals.remove(id);
+    }
+
+    // トークン所有数を1増やす
+    fn increase_balance(&mut self, of: &AccountId) -> Result<(), Error> {
+        self.increase_balance_by(of, 1)
+    }
+
+    // トークン所有数をn増やす。書き込みは一回だけ
+    fn increase_balance_by(&mut self, of: &AccountId, n: u64) -> Result<(), Error> {
+        let before = self.balance_of_or_zero(of);
+        let count = before.checked_add(n).ok_or(Error::Overflow)?;
+        self.data_mut().owned_tokens_count.insert(of, &count);
+        self.after_balance_changed(of, before, count);
+        Ok(())
+    }
+
+    // トークン所有数を1減らす
+    fn decrease_balance(&mut self, of: &AccountId) -> Result<(), Error> {
+        self.decrease_balance_by(of, 1)
+    }
+
+    // トークン所有数をn減らす。書き込みは一回だけ
+    fn decrease_balance_by(&mut self, of: &AccountId, n: u64) -> Result<(), Error> {
+        let before = self
+            .data()
+            .owned_tokens_count
+            .get(of) // トークンの所有数
+            .ok_or(Error::CannotFetchValue)?; // 見つからなかったらエラー返す
+        let count = before.checked_sub(n).ok_or(Error::Underflow)?;
+        self.data_mut().owned_tokens_count.insert(of, &count);
+        self.after_balance_changed(of, before, count);
+        Ok(())
+    }
+
+    fn add_token_to(&mut self, to: &AccountId, id: TokenId) -> Result<(), Error> {
+        self.add_tokens_to(to, &[id])
+    }
+
+    // まとめてトークンを追加する。所有数の更新は一回だけ
+    fn add_tokens_to(&mut self, to: &AccountId, ids: &[TokenId]) -> Result<(), Error> {
+        // 既にトークン誰か持ってる
+        if ids.iter().any(|id| self.exists(*id)) {
+            return Err(Error::TokenExists);
+        }
+
+        ensure_not_zero(to)?;
+
+        self.increase_balance_by(to, ids.len() as u64)?;
+        for id in ids {
+            self.data_mut().token_owner.insert(id, to);
+        }
+        self.after_tokens_added(to, ids);
+
+        Ok(())
+    }
+
+    fn remove_token_from(&mut self, from: &AccountId, id: TokenId) -> Result<(), Error> {
+        // 所有者を確認してから削除する
+        if self.owner_or_err(id)? != *from {
+            return Err(Error::NotOwner);
+        }
+        self.data_mut().token_owner.remove(id);
+
+        self.decrease_balance(from)?;
+        self.after_tokens_removed(from, &[id]);
+        Ok(())
+    }
+
+    // まとめてトークンを削除する。所有数の更新は一回だけ
+    // idsに重複がないことは呼び出し側で確認する
+    fn remove_tokens_from(&mut self, from: &AccountId, ids: &[TokenId]) -> Result<(), Error> {
+        // 全部fromのものか先に確認する
+        for id in ids {
+            if self.owner_or_err(*id)? != *from {
+                return Err(Error::NotOwner);
+            }
+        }
+
+        for id in ids {
+            self.data_mut().token_owner.remove(id);
+        }
+        self.decrease_balance_by(from, ids.len() as u64)?;
+        self.after_tokens_removed(from, ids);
+        Ok(())
+    }
+}
+
+// ゼロアドレス(AccountIdはconstで作れないのでバイト列で持つ)
+pub const ZERO_ACCOUNT: [u8; 32] = [0x0; 32];
+
+pub fn is_zero(account: &AccountId) -> bool {
+    *account == AccountId::from(ZERO_ACCOUNT)
+}
+
+// ゼロアドレスには送れない・Approveできない
+pub fn ensure_not_zero(account: &AccountId) -> Result<(), Error> {
+    if is_zero(account) {
+        return Err(Error::ZeroAddressRecipient);
+    }
+    Ok(())
+}
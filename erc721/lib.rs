@@ -1,46 +1,648 @@
 // 標準ライブラリがなかったら標準ライブラリを使わない宣言
 #![cfg_attr(not(feature = "std"), no_std)]
 
+pub mod internal;
+pub mod psp22;
+pub mod psp34;
+#[cfg(feature = "randomness")]
+pub mod randomness;
+pub mod receiver;
+
+// Mappingのストレージキー
+// manual-keysフィーチャーを有効にするとアクセスのされ方ごとにまとめた固定キーを使う
+// (上位16bit: 0x0001 = TokenIdがキー, 0x0002 = AccountIdがキー, 0x0003 = 単一の値, 0x0004 = 通し番号がキー,
+//  0x0005 = 属性の(キー, 値)がキー)
+// 固定キーにしておくとフィールドを追加・並べ替えてもレイアウトが変わらない
+#[cfg(feature = "manual-keys")]
+pub(crate) mod keys {
+    use ink::storage::traits::ManualKey;
+
+    pub type TokenOwner = ManualKey<0x0001_0001>;
+    pub type TokenApprovals = ManualKey<0x0001_0002>;
+    pub type TokenNotes = ManualKey<0x0001_0003>;
+    pub type TransferCounts = ManualKey<0x0001_0004>;
+    pub type TokenTimestamps = ManualKey<0x0001_0005>;
+    pub type Minters = ManualKey<0x0001_0006>;
+    pub type UriOverrides = ManualKey<0x0001_0007>;
+    pub type LiveBitmap = ManualKey<0x0001_0008>;
+    pub type AllTokensIndex = ManualKey<0x0001_0009>;
+    pub type OwnedTokensIndex = ManualKey<0x0001_000a>;
+    pub type Attributes = ManualKey<0x0001_000b>;
+    pub type AttributeKeys = ManualKey<0x0001_000c>;
+    pub type TokenUris = ManualKey<0x0001_000d>;
+    pub type Seeds = ManualKey<0x0001_000e>;
+    pub type AttributeTokensIndex = ManualKey<0x0001_000f>;
+    pub type FrozenMetadata = ManualKey<0x0001_0010>;
+    pub type TokenNames = ManualKey<0x0001_0011>;
+    pub type TokenDescriptions = ManualKey<0x0001_0012>;
+    pub type ExternalUrls = ManualKey<0x0001_0013>;
+    pub type AnimationUrls = ManualKey<0x0001_0014>;
+    pub type ArtistSignatures = ManualKey<0x0001_0015>;
+    pub type Rerolled = ManualKey<0x0001_0016>;
+    pub type TokenPalettes = ManualKey<0x0001_0017>;
+    pub type SeedSources = ManualKey<0x0001_0018>;
+    pub type TokenEditions = ManualKey<0x0001_0019>;
+    pub type OwnedTokensCount = ManualKey<0x0002_0001>;
+    pub type OperatorApprovals = ManualKey<0x0002_0002>;
+    pub type Operators = ManualKey<0x0002_0003>;
+    pub type Moderators = ManualKey<0x0002_0004>;
+    pub type OwnedTokens = ManualKey<0x0002_0005>;
+    pub type CharityOwed = ManualKey<0x0002_0006>;
+    pub type TopHolders = ManualKey<0x0003_0001>;
+    pub type ContractUri = ManualKey<0x0003_0002>;
+    pub type BaseCid = ManualKey<0x0003_0003>;
+    pub type PlaceholderUri = ManualKey<0x0003_0005>;
+    pub type RarityTable = ManualKey<0x0003_0006>;
+    pub type AnimationBaseUrl = ManualKey<0x0003_0007>;
+    pub type Artist = ManualKey<0x0003_0008>;
+    pub type RerollPrice = ManualKey<0x0003_0009>;
+    pub type PaletteCount = ManualKey<0x0003_000a>;
+    pub type ScriptHash = ManualKey<0x0003_000b>;
+    pub type UriTemplate = ManualKey<0x0003_000c>;
+    pub type CharityOwedTotal = ManualKey<0x0003_000d>;
+    pub type AllTokens = ManualKey<0x0004_0001>;
+    pub type ScriptChunks = ManualKey<0x0004_0002>;
+    pub type Palettes = ManualKey<0x0004_0003>;
+    pub type Editions = ManualKey<0x0004_0004>;
+    pub type AttributeTokens = ManualKey<0x0005_0001>;
+    pub type AttributeTokensCount = ManualKey<0x0005_0002>;
+}
+
+// デフォルトはフィールド名から自動でキーを決める
+#[cfg(not(feature = "manual-keys"))]
+pub(crate) mod keys {
+    use ink::storage::traits::AutoKey;
+
+    pub type TokenOwner = AutoKey;
+    pub type TokenApprovals = AutoKey;
+    pub type TokenNotes = AutoKey;
+    pub type TransferCounts = AutoKey;
+    pub type TokenTimestamps = AutoKey;
+    pub type Minters = AutoKey;
+    pub type UriOverrides = AutoKey;
+    pub type LiveBitmap = AutoKey;
+    pub type AllTokensIndex = AutoKey;
+    pub type OwnedTokensIndex = AutoKey;
+    pub type Attributes = AutoKey;
+    pub type AttributeKeys = AutoKey;
+    pub type TokenUris = AutoKey;
+    pub type Seeds = AutoKey;
+    pub type AttributeTokensIndex = AutoKey;
+    pub type FrozenMetadata = AutoKey;
+    pub type TokenNames = AutoKey;
+    pub type TokenDescriptions = AutoKey;
+    pub type ExternalUrls = AutoKey;
+    pub type AnimationUrls = AutoKey;
+    pub type ArtistSignatures = AutoKey;
+    pub type Rerolled = AutoKey;
+    pub type TokenPalettes = AutoKey;
+    pub type SeedSources = AutoKey;
+    pub type TokenEditions = AutoKey;
+    pub type OwnedTokensCount = AutoKey;
+    pub type OperatorApprovals = AutoKey;
+    pub type Operators = AutoKey;
+    pub type Moderators = AutoKey;
+    pub type OwnedTokens = AutoKey;
+    pub type CharityOwed = AutoKey;
+    pub type TopHolders = AutoKey;
+    pub type ContractUri = AutoKey;
+    pub type BaseCid = AutoKey;
+    pub type PlaceholderUri = AutoKey;
+    pub type RarityTable = AutoKey;
+    pub type AnimationBaseUrl = AutoKey;
+    pub type Artist = AutoKey;
+    pub type RerollPrice = AutoKey;
+    pub type PaletteCount = AutoKey;
+    pub type ScriptHash = AutoKey;
+    pub type UriTemplate = AutoKey;
+    pub type CharityOwedTotal = AutoKey;
+    pub type AllTokens = AutoKey;
+    pub type ScriptChunks = AutoKey;
+    pub type Palettes = AutoKey;
+    pub type Editions = AutoKey;
+    pub type AttributeTokens = AutoKey;
+    pub type AttributeTokensCount = AutoKey;
+}
+
 // Contract定義のエントリーポイント
-#[ink::contract]
+// randomnessフィーチャーでは乱数のチェーンエクステンションを持つ環境を使う
+#[cfg_attr(
+    feature = "randomness",
+    ink::contract(env = crate::randomness::Erc721Environment)
+)]
+#[cfg_attr(not(feature = "randomness"), ink::contract)]
 mod erc721 {
-    use ink::prelude::string::{String, ToString};
+    use ink::prelude::string::String;
+    use ink::prelude::vec::Vec;
+    use ink::storage::Lazy;
     use ink::storage::Mapping; // inkからMapping structをimport.スマートコントラクト用に用意されているのでMapにはこれを使う。
     use scale::{Decode, Encode};
 
-    pub type TokenId = u32; // TokenId
+    use crate::internal::{self, Erc721Internal};
+    pub use crate::internal::{Error, TokenId};
+    use crate::keys;
+    use crate::psp22::PSP22Error;
+    use crate::psp34::{Id, PSP34Enumerable, PSP34Error, PSP34Metadata, PSP34};
+    use crate::receiver::ON_ERC721_RECEIVED_SELECTOR;
+
+    // (アカウント, 所有数)の降順リスト
+    pub type HolderRanking = Vec<(AccountId, u64)>;
+
+    // (属性のキー, 値, 番号)
+    pub type AttributeSlot = (String, String, u32);
+
+    // 属性のキーごとの(値, 重み)の一覧
+    pub type RarityTable = Vec<(String, Vec<(String, u32)>)>;
+
+    // シードの材料(mintを呼んだアカウント, ブロック番号, mint者が渡したエントロピー)
+    pub type SeedSource = (AccountId, BlockNumber, Vec<u8>);
+
+    // (パレットの名前, RGBの色の一覧)
+    pub type Palette = (String, Vec<[u8; 3]>);
+
+    // 1回のメッセージで扱える件数の初期値
+    const DEFAULT_MAX_BATCH_SIZE: u32 = 50;
+
+    // 連続mintで1個あたりに見込むgas(ref_time)と、最後の後始末のために残すgas
+    // bench_mint_batchで測った値を目安にする
+    const MINT_GAS_PER_TOKEN: u64 = 1_000_000_000;
+    const MINT_GAS_RESERVE: u64 = 5_000_000_000;
+
+    // ランキングに載せるアカウントの数
+    const TOP_HOLDERS_LEN: usize = 20;
+
+    // 1アカウントが全承認できるオペレーターの上限
+    const MAX_OPERATORS: usize = 32;
+
+    // PSP22::transfer / PSP34::transfer のセレクタ(blake2b("PSP22::transfer")の先頭4バイト)
+    const PSP22_TRANSFER_SELECTOR: [u8; 4] = [0xdb, 0x20, 0xf9, 0xf5];
+    const PSP34_TRANSFER_SELECTOR: [u8; 4] = [0x31, 0x28, 0xd6, 0x1b];
+
+    // ERC-165のインターフェースID
+    const INTERFACE_ID_ERC165: [u8; 4] = [0x01, 0xff, 0xc9, 0xa7];
+    const INTERFACE_ID_ERC721: [u8; 4] = [0x80, 0xac, 0x58, 0xcd];
+    const INTERFACE_ID_ERC721_METADATA: [u8; 4] = [0x5b, 0x5e, 0x13, 0x9f];
+    const INTERFACE_ID_ERC721_ENUMERABLE: [u8; 4] = [0x78, 0x0e, 0x9d, 0x63];
+    const INTERFACE_ID_ERC4906: [u8; 4] = [0x49, 0x06, 0x49, 0x06];
+
+    // 1トークンに残せるメモの数と1つあたりの最大バイト数
+    const MAX_NOTES: usize = 16;
+    const MAX_NOTE_LEN: usize = 140;
+
+    // token_htmlで読み込むp5.js本体
+    const P5_LIBRARY_URL: &str = "https://cdn.jsdelivr.net/npm/p5@1.9.0/lib/p5.min.js";
+
+    // スクリプトのチャンク1つあたりの最大バイト数
+    const MAX_SCRIPT_CHUNK_LEN: usize = 8 * 1024;
+
+    // CIDの長さの範囲(CIDv0は46文字、CIDv1はそれより長い)
+    const MIN_CID_LEN: usize = 46;
+    const MAX_CID_LEN: usize = 128;
+
+    // 所有者が付けられる名前と説明の最大バイト数
+    const MAX_TOKEN_NAME_LEN: usize = 64;
+    const MAX_TOKEN_DESCRIPTION_LEN: usize = 1024;
+
+    // external_urlの最大バイト数
+    const MAX_URL_LEN: usize = 256;
+
+    // アーティスト署名の最大バイト数(sr25519は64、ecdsaは65)
+    const MAX_SIGNATURE_LEN: usize = 128;
+
+    // 1トークンに持たせられる属性の数と、キー・値の最大バイト数
+    const MAX_ATTRIBUTES: usize = 32;
+    const MAX_ATTRIBUTE_KEY_LEN: usize = 32;
+    const MAX_ATTRIBUTE_VALUE_LEN: usize = 256;
+
+    // レア度の表で1つのキーに並べられる値の数
+    const MAX_RARITY_VALUES: usize = 32;
+
+    // パレットの名前の最大バイト数と、1つのパレットの色の数
+    const MAX_PALETTE_NAME_LEN: usize = 32;
+    const MAX_PALETTE_COLORS: usize = 16;
+
+    // mint者がシードに混ぜられるエントロピーの最大バイト数
+    const MAX_ENTROPY_LEN: usize = 32;
+
+    // transferに付けられるメモの最大バイト数
+    const MAX_MEMO_LEN: usize = 140;
+
+    // ロイヤリティの分母(basis point。10000で100%)
+    const ROYALTY_DENOMINATOR: u16 = 10_000;
+
+    // ストレージのレイアウトを変えたら上げる
+    const STORAGE_VERSION: u16 = 8;
+
+    // ビルド時に有効になっている拡張機能
+    const FEATURES: &[&str] = &[
+        #[cfg(feature = "royalties")]
+        "royalties",
+        #[cfg(feature = "enumerable")]
+        "enumerable",
+        #[cfg(feature = "fixed-supply")]
+        "fixed-supply",
+        #[cfg(feature = "manual-keys")]
+        "manual-keys",
+        #[cfg(feature = "bench")]
+        "bench",
+        #[cfg(feature = "randomness")]
+        "randomness",
+    ];
+
+    // TokenIdを10進数にしたときの最大桁数(u128::MAX = 340282366920938463463374607431768211455)
+    const MAX_ID_DIGITS: usize = 39;
+
+    // fixed-supplyフィーチャーではビルド時に環境変数ERC721_MAX_SUPPLYで上限を埋め込む
+    // コードハッシュに含まれるのでオーナーでも後から変えられない
+    #[cfg(feature = "fixed-supply")]
+    const FIXED_MAX_SUPPLY: TokenId = match option_env!("ERC721_MAX_SUPPLY") {
+        Some(value) => parse_id(value),
+        None => panic!(
+            "the fixed-supply feature needs ERC721_MAX_SUPPLY at build time \
+             (e.g. ERC721_MAX_SUPPLY=10000 cargo contract build --features fixed-supply)"
+        ),
+    };
+
+    // 10進数の文字列をTokenIdにする。constで使うので不正な値はコンパイルエラーになる
+    #[cfg_attr(not(any(test, feature = "fixed-supply")), allow(dead_code))]
+    const fn parse_id(s: &str) -> TokenId {
+        let bytes = s.as_bytes();
+        assert!(!bytes.is_empty(), "empty token id");
+        let mut n: TokenId = 0;
+        let mut i = 0;
+        while i < bytes.len() {
+            assert!(bytes[i].is_ascii_digit(), "invalid token id");
+            n = match n.checked_mul(10) {
+                Some(n) => n,
+                None => panic!("token id overflow"),
+            };
+            n = match n.checked_add((bytes[i] - b'0') as TokenId) {
+                Some(n) => n,
+                None => panic!("token id overflow"),
+            };
+            i += 1;
+        }
+        n
+    }
+
+    // ヒープを使わずにTokenIdを10進数の文字列にする
+    fn format_id(id: TokenId, buf: &mut [u8; MAX_ID_DIGITS]) -> &str {
+        let mut n = id;
+        let mut pos = MAX_ID_DIGITS;
+        loop {
+            pos -= 1;
+            buf[pos] = b'0' + (n % 10) as u8;
+            n /= 10;
+            if n == 0 {
+                break;
+            }
+        }
+        // 数字しか書き込んでないので失敗しない
+        core::str::from_utf8(&buf[pos..]).unwrap_or_default()
+    }
+
+    // JSONの文字列リテラルとして書き出す(引用符・バックスラッシュ・制御文字をエスケープ)
+    fn push_json_str(out: &mut String, value: &str) {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+        out.push('"');
+        for c in value.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                // HTMLの<script>に埋め込んでも閉じタグにならないようにする
+                '<' => out.push_str("\\u003c"),
+                c if (c as u32) < 0x20 => {
+                    out.push_str("\\u00");
+                    out.push(char::from(HEX[(c as usize) >> 4]));
+                    out.push(char::from(HEX[(c as usize) & 0xf]));
+                }
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+    }
+
+    // 他のコントラクトのtransferの結果。呼び出し先がErrを返すと向こうの変更が戻るだけで
+    // こちらはrevertされないので、中のResultまで確かめる
+    fn foreign_transfer_result<E>(
+        result: Result<ink::MessageResult<Result<(), E>>, ink::env::Error>,
+    ) -> Result<(), Error> {
+        match result {
+            Ok(Ok(Ok(()))) => Ok(()),
+            _ => Err(Error::TransferFailed),
+        }
+    }
+
+    // HTMLのテキストや属性値として書き出す(<, >, &, "をエスケープ)
+    fn push_html_text(out: &mut String, value: &str) {
+        for c in value.chars() {
+            match c {
+                '<' => out.push_str("&lt;"),
+                '>' => out.push_str("&gt;"),
+                '&' => out.push_str("&amp;"),
+                '"' => out.push_str("&quot;"),
+                c => out.push(c),
+            }
+        }
+    }
+
+    // バイト列を16進数の文字列にする(0xは付けない)
+    fn push_hex(out: &mut String, bytes: &[u8]) {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+        for b in bytes {
+            out.push(char::from(HEX[usize::from(b >> 4)]));
+            out.push(char::from(HEX[usize::from(b & 0xf)]));
+        }
+    }
+
+    // URIのテンプレートの{id}をidに、{seed}をシードの16進数(0xなし)に置き換える
+    // それ以外の{...}はそのまま残す
+    fn render_uri_template(template: &str, id: &str, seed: &[u8; 32]) -> String {
+        let mut uri = String::with_capacity(template.len() + 64);
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            uri.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if let Some(after) = rest.strip_prefix("{id}") {
+                uri.push_str(id);
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("{seed}") {
+                push_hex(&mut uri, seed);
+                rest = after;
+            } else {
+                uri.push('{');
+                rest = &rest[1..];
+            }
+        }
+        uri.push_str(rest);
+        uri
+    }
+
+    // シードから決まるSVG。背景色と3〜6個の円をシードのバイトから決める
+    fn seed_svg(seed: &[u8; 32]) -> String {
+        let mut buf = [0u8; MAX_ID_DIGITS];
+        let mut svg = String::from(
+            "<svg xmlns='http://www.w3.org/2000/svg' viewBox='0 0 256 256'><rect width='256' height='256' fill='#",
+        );
+        push_hex(&mut svg, &seed[0..3]);
+        svg.push_str("'/>");
+        let count = 3 + usize::from(seed[3] % 4);
+        for shape in seed[4..].chunks_exact(4).take(count) {
+            svg.push_str("<circle cx='");
+            svg.push_str(format_id(TokenId::from(shape[0]), &mut buf));
+            svg.push_str("' cy='");
+            svg.push_str(format_id(TokenId::from(shape[1]), &mut buf));
+            svg.push_str("' r='");
+            svg.push_str(format_id(TokenId::from(16 + shape[2] % 64), &mut buf));
+            svg.push_str("' fill='#");
+            push_hex(&mut svg, &shape[1..4]);
+            svg.push_str("' fill-opacity='0.8'/>");
+        }
+        svg.push_str("</svg>");
+        svg
+    }
+
+    // シードと属性の番号から重み付きで値を1つ選ぶ。重みの合計が0ならNone
+    fn pick_weighted(seed: &[u8; 32], salt: u32, entries: &[(String, u32)]) -> Option<usize> {
+        let total: u64 = entries.iter().map(|(_, weight)| u64::from(*weight)).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut hash = [0u8; 32];
+        ink::env::hash_encoded::<ink::env::hash::Blake2x256, _>(&(seed, salt), &mut hash);
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&hash[..8]);
+        let mut r = u64::from_le_bytes(bytes) % total;
+        entries.iter().position(|(_, weight)| {
+            if r < u64::from(*weight) {
+                return true;
+            }
+            r -= u64::from(*weight);
+            false
+        })
+    }
 
-    // metadata.jsonのあるとこ
-    const TOKEN_URI: &str = "https://example.com/";
+    // シードからcount個のパレットのどれかを選ぶ。パレットがなければNone
+    fn pick_palette(seed: &[u8; 32], count: u32) -> Option<u32> {
+        if count == 0 {
+            return None;
+        }
+        let mut hash = [0u8; 32];
+        ink::env::hash_encoded::<ink::env::hash::Blake2x256, _>(&(seed, b"palette"), &mut hash);
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&hash[..4]);
+        Some(u32::from_le_bytes(bytes) % count)
+    }
 
     // ストレージ定義
     #[ink(storage)]
-    #[derive(Default)] // Default traitを実装
     pub struct Erc721 {
-        token_owner: Mapping<TokenId, AccountId>,
-        token_approvals: Mapping<TokenId, AccountId>,
-        owned_tokens_count: Mapping<AccountId, u32>,
-        operator_approvals: Mapping<(AccountId, AccountId), ()>,
+        // 所有者・承認・所有数
+        erc721: internal::Data,
+        // 所有者が付けたトークンの名前と説明(転送されても引き継がれる)
+        token_names: Mapping<TokenId, String, keys::TokenNames>,
+        token_descriptions: Mapping<TokenId, String, keys::TokenDescriptions>,
+        // トークンに残す来歴メモ(転送されても引き継がれる)
+        token_notes: Mapping<TokenId, Vec<String>, keys::TokenNotes>,
+        // トークンが転送された回数(mintは含まない)
+        transfer_counts: Mapping<TokenId, u32, keys::TransferCounts>,
+        // (mintした時刻, 最後に転送された時刻)
+        token_timestamps: Mapping<TokenId, (Timestamp, Timestamp), keys::TokenTimestamps>,
+        // 最初にmintしたアカウント
+        minters: Mapping<TokenId, AccountId, keys::Minters>,
+        // モデレーターが差し替えたURI(削除要請への対応など)
+        uri_overrides: Mapping<TokenId, String, keys::UriOverrides>,
+        // メタデータのexternal_url(トークンごとのp5.jsのビューアーなど)
+        external_urls: Mapping<TokenId, String, keys::ExternalUrls>,
+        // メタデータのanimation_url(動くp5.jsの作品)。なければanimation_base_url + TokenId
+        animation_urls: Mapping<TokenId, String, keys::AnimationUrls>,
+        // アーティストがトークンごとに付けた署名
+        artist_signatures: Mapping<TokenId, Vec<u8>, keys::ArtistSignatures>,
+        // シードを引き直したトークン
+        rerolled: Mapping<TokenId, (), keys::Rerolled>,
+        // mintのときに選ばれたパレットの番号
+        token_palettes: Mapping<TokenId, u32, keys::TokenPalettes>,
+        // 登録順に番号を振ったパレット。一度登録したら変更しない
+        palettes: Mapping<u32, Palette, keys::Palettes>,
+        // 1点物などのために個別に指定したURI(モデレーターの差し替えの方が優先)
+        token_uris: Mapping<TokenId, String, keys::TokenUris>,
+        // メタデータ(URI・属性)を固定したトークン
+        frozen_metadata: Mapping<TokenId, (), keys::FrozenMetadata>,
+        // p5.jsのスケッチに渡す乱数のシード(mint時に決める)
+        seeds: Mapping<TokenId, [u8; 32], keys::Seeds>,
+        // mintのときのシードの材料。誰でもシードを計算し直して確かめられる
+        seed_sources: Mapping<TokenId, SeedSource, keys::SeedSources>,
+        // エディションのトークンの(エディションID, 通し番号)。通し番号は1から
+        token_editions: Mapping<TokenId, (u32, u32), keys::TokenEditions>,
+        // エディションごとの(最初のTokenId, 枚数)
+        editions: Mapping<u32, (TokenId, u32), keys::Editions>,
+        // 存在するトークンのビットマップ(256個ごとに1ワード)
+        live_bitmap: Mapping<TokenId, [u64; 4], keys::LiveBitmap>,
+        // モデレーター権限を持つアカウント
+        moderators: Mapping<AccountId, (), keys::Moderators>,
+        // 所有数の多いアカウント(降順)。毎回読み込まないようにLazyにする
+        top_holders: Lazy<HolderRanking, keys::TopHolders>,
+        // 全トークンの一覧(通し番号 -> TokenId)。burnしたら最後の要素で穴を埋める
+        all_tokens: Mapping<u64, TokenId, keys::AllTokens>,
+        // all_tokensでの位置
+        all_tokens_index: Mapping<TokenId, u64, keys::AllTokensIndex>,
+        // 所有者ごとのトークン一覧((所有者, 番号) -> TokenId)。all_tokensと同じく穴は最後の要素で埋める
+        owned_tokens: Mapping<(AccountId, u64), TokenId, keys::OwnedTokens>,
+        // owned_tokensでの位置
+        owned_tokens_index: Mapping<TokenId, u64, keys::OwnedTokensIndex>,
+        // コレクションのmetadata.json(マーケットプレイスのコレクションページ用)
+        contract_uri: Lazy<String, keys::ContractUri>,
+        // メタデータを置いたIPFSのディレクトリのCID。空でなければベースURIより優先する
+        base_cid: Lazy<String, keys::BaseCid>,
+        // リビール前に全トークンが返すURI
+        placeholder_uri: Lazy<String, keys::PlaceholderUri>,
+        // mint時にシードから属性を選ぶためのレア度の表
+        rarity_table: Lazy<RarityTable, keys::RarityTable>,
+        // animation_urlの既定値のベース
+        animation_base_url: Lazy<String, keys::AnimationBaseUrl>,
+        // トークンに署名できるアーティスト
+        artist: Lazy<AccountId, keys::Artist>,
+        // シードの引き直しの代金
+        reroll_price: Lazy<Balance, keys::RerollPrice>,
+        // 登録されたパレットの数
+        palette_count: Lazy<u32, keys::PaletteCount>,
+        // p5.jsのスケッチのsha256
+        script_hash: Lazy<[u8; 32], keys::ScriptHash>,
+        // token_uriのテンプレート({id}と{seed}を置き換える)
+        uri_template: Lazy<String, keys::UriTemplate>,
+        // 寄付先を変える前に貯まっていて、まだ送っていない額(前の寄付先ごと)
+        charity_owed: Mapping<AccountId, Balance, keys::CharityOwed>,
+        // charity_owedの合計(オーナーは引き出せない)
+        charity_owed_total: Lazy<Balance, keys::CharityOwedTotal>,
+        // トークンを描くp5.jsのスケッチ(seedとattributesを参照する)
+        // 1回の呼び出しに収まらないので分割してアップロードする
+        script_chunks: Mapping<u32, Vec<u8>, keys::ScriptChunks>,
+        // 所有者ごとの全承認しているオペレーター一覧(operator_approvalsの索引)
+        operators: Mapping<AccountId, Vec<AccountId>, keys::Operators>,
+        // トークンの属性((TokenId, キー) -> 値)。パレットや図形の数などをチェーン上に置く
+        attributes: Mapping<(TokenId, String), String, keys::Attributes>,
+        // トークンに設定されている属性のキー一覧(burnで消すための索引)
+        attribute_keys: Mapping<TokenId, Vec<String>, keys::AttributeKeys>,
+        // 属性ごとのトークン一覧((キー, 値, 番号) -> TokenId)。all_tokensと同じく穴は最後の要素で埋める
+        attribute_tokens: Mapping<AttributeSlot, TokenId, keys::AttributeTokens>,
+        // attribute_tokensの件数
+        attribute_tokens_count: Mapping<(String, String), u32, keys::AttributeTokensCount>,
+        // attribute_tokensでの位置(トークンはキーごとに値を1つしか持たない)
+        attribute_tokens_index: Mapping<(TokenId, String), u32, keys::AttributeTokensIndex>,
+        // 最初にmintしたTokenId
+        first_id: TokenId,
+        // 次にmintするTokenId
         token_id: TokenId,
+        // mintごとにTokenIdを進める幅
+        id_step: TokenId,
+        // mintされたトークンの数
+        minted_count: u64,
+        // burnされたトークンの数
+        burned_count: u64,
+        // トークンを1つ以上持っているアカウントの数
+        holder_count: u64,
+        // コントラクトをデプロイしたアカウント
+        owner: AccountId,
+        name: String,
+        symbol: String,
+        // metadata.jsonのあるとこ
+        base_uri: String,
+        // mintできるトークンの上限
+        max_supply: TokenId,
+        // mint1回あたりの価格
+        mint_price: Balance,
+        // 二次流通のロイヤリティ(basis point)
+        royalty: u16,
+        // ロイヤリティのうち最初のmint者に分ける割合(basis point)
+        minter_royalty_share: u16,
+        // 1回のメッセージで扱える件数の上限
+        max_batch_size: u32,
+        // mintの売上の一部を寄付する先
+        charity: Option<AccountId>,
+        // 寄付する割合(basis point)
+        charity_share: u16,
+        // 寄付先にまだ送っていない額(オーナーは引き出せない)
+        charity_balance: Balance,
+        // これまでに寄付した合計
+        charity_donated: Balance,
+        // アップロードされたスクリプトのチャンク数
+        script_size: u32,
+        // リビール済みか
+        revealed: bool,
+        // 全トークンのメタデータを固定したか(元に戻せない)
+        metadata_frozen: bool,
+        // リビール時に決めた、mint順とメタデータの番号のずれ
+        reveal_offset: TokenId,
+        // ベースURIのあとに.jsonを付けるか
+        uses_json_suffix: bool,
+    }
+
+    // ベンチマーク結果(メッセージのシグネチャから参照されるのでcfgはつけない)
+    #[derive(Encode, Decode, Debug, PartialEq, Eq, Clone, Copy)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct BenchReport {
+        // 消費したgas
+        pub gas: u64,
+        // 触ったストレージのバイト数の増減
+        pub storage_bytes: i64,
+    }
+
+    // デプロイされているコントラクトの情報
+    #[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct ContractInfo {
+        // クレートのバージョン
+        pub version: String,
+        // 有効な拡張機能
+        pub features: Vec<String>,
+        pub storage_version: u16,
+        // 取得できなければNone
+        pub code_hash: Option<Hash>,
+    }
+
+    // ストレージデポジットの見積もり
+    // 1アイテム・1バイトあたりの単価はチェーンごとに違うのでウォレット側で掛ける
+    #[derive(Encode, Decode, Debug, PartialEq, Eq, Clone, Copy, Default)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct StorageEstimate {
+        // 新しく作られるストレージのエントリ数
+        pub items: u32,
+        // 新しく書き込まれる値のバイト数
+        pub bytes: u32,
     }
 
-    // エラー定義
-    #[derive(Encode, Decode, Debug, PartialEq, Eq, Clone, Copy)] // いろいろtraitを実装
+    // メタデータを見せるタイミング
+    #[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
     #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
-    pub enum Error {
-        NotOwner,
-        NotApproved,
-        TokenExists,
-        TokenNotFound,
-        CannotInsert,
-        CannotFetchValue,
-        NotAllowed,
+    pub enum RevealMode {
+        // 最初からリビール済み
+        Immediate,
+        // revealを呼ぶまでplaceholder_uriを返す(空ならプレースホルダーなし)
+        Delayed { placeholder_uri: String },
+    }
+
+    // インスタンス化のときのコレクションの設定
+    // 同じコードハッシュから設定の違うコレクションをいくつも作れる
+    #[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub struct MetadataConfig {
+        pub name: String,
+        pub symbol: String,
+        pub base_uri: String,
+        // trueならベースURIのあとに<id>.jsonを付ける
+        pub uses_json_suffix: bool,
+        pub reveal_mode: RevealMode,
+        pub max_supply: TokenId,
     }
 
     // イベント定義
 
     // トークンがTransferされたときのイベント
     #[ink(event)]
+    // mintならfromが、burnならtoがNoneになる
     pub struct Transfer {
         #[ink(topic)] // indexedを追加
         from: Option<AccountId>,
@@ -48,6 +650,8 @@ mod erc721 {
         to: Option<AccountId>,
         #[ink(topic)]
         id: TokenId,
+        // 送り手が付けたメモ(ストレージには保存しない)
+        memo: Option<String>,
     }
 
     // 承認されたときのイベント
@@ -61,6 +665,193 @@ mod erc721 {
         id: TokenId,
     }
 
+    // gasが足りず連続mintを途中で止めたときのイベント
+    // 続きはremaining個をもう一度mintする(TokenIdは連番なのでnext_idから続く)
+    #[ink(event)]
+    pub struct BatchMintTruncated {
+        #[ink(topic)]
+        to: AccountId,
+        minted: u32,
+        remaining: u32,
+        next_id: TokenId,
+    }
+
+    // コントラクトに資金が追加されたときのイベント
+    #[ink(event)]
+    pub struct Funded {
+        #[ink(topic)]
+        from: AccountId,
+        amount: Balance,
+    }
+
+    // ベースURIが変更されたときのイベント
+    #[ink(event)]
+    pub struct BaseUriChanged {
+        base_uri: String,
+    }
+
+    // トークンのメタデータが固定されたときのイベント
+    #[ink(event)]
+    pub struct MetadataFrozen {
+        #[ink(topic)]
+        id: TokenId,
+        // 固定されたURI
+        uri: String,
+    }
+
+    // メタデータが変更できなくなったことをマーケットプレイスに伝えるイベント(OpenSeaのPermanentURIと同じ形)
+    #[ink(event)]
+    pub struct PermanentURI {
+        value: String,
+        #[ink(topic)]
+        id: TokenId,
+    }
+
+    // リビールされたときのイベント
+    #[ink(event)]
+    pub struct Revealed {
+        base_uri: String,
+        offset: TokenId,
+    }
+
+    // ベースCIDが変更されたときのイベント
+    #[ink(event)]
+    pub struct BaseCidChanged {
+        // 空ならベースURIに戻す
+        base_cid: String,
+    }
+
+    // コレクションのURIが変更されたときのイベント
+    #[ink(event)]
+    pub struct ContractUriChanged {
+        contract_uri: String,
+    }
+
+    // 寄付先・割合が変更されたときのイベント
+    #[ink(event)]
+    pub struct CharityChanged {
+        #[ink(topic)]
+        charity: Option<AccountId>,
+        share: u16,
+    }
+
+    // 寄付先に送金したときのイベント
+    #[ink(event)]
+    pub struct CharityPaid {
+        #[ink(topic)]
+        charity: AccountId,
+        amount: Balance,
+    }
+
+    // トークンのメタデータが変わったときのイベント(ERC-4906)
+    // マーケットプレイスはこれを見てメタデータを取り直す
+    #[ink(event)]
+    pub struct MetadataUpdate {
+        #[ink(topic)]
+        id: TokenId,
+    }
+
+    // from_idからto_idまでのメタデータがまとめて変わったときのイベント(ERC-4906)
+    #[ink(event)]
+    pub struct BatchMetadataUpdate {
+        from_id: TokenId,
+        to_id: TokenId,
+    }
+
+    // スケッチのハッシュが設定されたときのイベント
+    #[ink(event)]
+    pub struct ScriptHashSet {
+        hash: [u8; 32],
+    }
+
+    // エディションがmintされたときのイベント
+    #[ink(event)]
+    pub struct EditionMinted {
+        #[ink(topic)]
+        edition_id: u32,
+        first_id: TokenId,
+        size: u32,
+    }
+
+    // パレットが登録されたときのイベント
+    #[ink(event)]
+    pub struct PaletteAdded {
+        #[ink(topic)]
+        id: u32,
+        name: String,
+    }
+
+    // トークンのシードが引き直されたときのイベント
+    #[ink(event)]
+    pub struct SeedRerolled {
+        #[ink(topic)]
+        id: TokenId,
+        seed: [u8; 32],
+    }
+
+    // アーティストがトークンに署名したときのイベント
+    #[ink(event)]
+    pub struct TokenSigned {
+        #[ink(topic)]
+        id: TokenId,
+        #[ink(topic)]
+        artist: AccountId,
+        signature: Vec<u8>,
+    }
+
+    // トークンの属性が変更されたときのイベント
+    #[ink(event)]
+    pub struct AttributeSet {
+        #[ink(topic)]
+        id: TokenId,
+        key: String,
+        // Noneなら削除
+        value: Option<String>,
+    }
+
+    // トークンのURIが個別に指定された・解除されたときのイベント
+    #[ink(event)]
+    pub struct TokenUriSet {
+        #[ink(topic)]
+        id: TokenId,
+        // NoneならベースURI + TokenIdに戻す
+        uri: Option<String>,
+    }
+
+    // モデレーターがトークンのURIを差し替えた・戻したときのイベント
+    #[ink(event)]
+    pub struct TokenUriOverridden {
+        #[ink(topic)]
+        id: TokenId,
+        #[ink(topic)]
+        moderator: AccountId,
+        // Noneなら差し替えを解除
+        uri: Option<String>,
+    }
+
+    // burnされたときのイベント。operatorは実際に呼び出したアカウント
+    #[ink(event)]
+    pub struct Burned {
+        #[ink(topic)]
+        id: TokenId,
+        #[ink(topic)]
+        owner: AccountId,
+        #[ink(topic)]
+        operator: AccountId,
+    }
+
+    // トークンに対して投げ銭されたときのイベント
+    #[ink(event)]
+    pub struct Tipped {
+        #[ink(topic)]
+        id: TokenId,
+        #[ink(topic)]
+        from: AccountId,
+        #[ink(topic)]
+        to: AccountId,
+        amount: Balance,
+    }
+
     #[ink(event)]
     pub struct ApprovalForAll {
         #[ink(topic)]
@@ -73,15 +864,146 @@ mod erc721 {
     // コントラクトの実装
     impl Erc721 {
         // コンストラクタ
+        // 同じWasmを別のコレクションでも使えるように設定は引数で受け取る
+        #[ink(constructor)]
+        #[allow(clippy::too_many_arguments)]
+        pub fn new(
+            name: String,
+            symbol: String,
+            base_uri: String,
+            max_supply: TokenId,
+            mint_price: Balance,
+            royalty: u16,
+            first_id: TokenId,
+            id_step: TokenId,
+        ) -> Result<Self, Error> {
+            // これまで通り、revealを呼べてプレースホルダーはなし
+            let config = MetadataConfig {
+                name,
+                symbol,
+                base_uri,
+                uses_json_suffix: false,
+                reveal_mode: RevealMode::Delayed {
+                    placeholder_uri: String::new(),
+                },
+                max_supply,
+            };
+            Self::with_config(config, mint_price, royalty, first_id, id_step)
+        }
+
+        // MetadataConfigでコレクションを設定してインスタンス化する
         #[ink(constructor)]
-        pub fn new() -> Self {
-            Erc721 {
-                token_owner: Default::default(),
-                token_approvals: Default::default(),
-                owned_tokens_count: Default::default(),
-                operator_approvals: Default::default(),
-                token_id: 1, // 最初は１から
+        pub fn with_config(
+            config: MetadataConfig,
+            mint_price: Balance,
+            royalty: u16,
+            first_id: TokenId,
+            id_step: TokenId,
+        ) -> Result<Self, Error> {
+            let MetadataConfig {
+                name,
+                symbol,
+                base_uri,
+                uses_json_suffix,
+                reveal_mode,
+                max_supply,
+            } = config;
+            if name.is_empty()
+                || symbol.is_empty()
+                || base_uri.is_empty()
+                || max_supply == 0
+                || royalty > ROYALTY_DENOMINATOR
+                || id_step == 0
+            {
+                return Err(Error::InvalidParameter);
+            }
+            let (revealed, placeholder_uri) = match reveal_mode {
+                RevealMode::Immediate => (true, String::new()),
+                RevealMode::Delayed { placeholder_uri } => (false, placeholder_uri),
+            };
+
+            // ビルド時に埋め込んだ上限と違う
+            #[cfg(feature = "fixed-supply")]
+            if max_supply != FIXED_MAX_SUPPLY {
+                return Err(Error::InvalidParameter);
+            }
+
+            let mut erc721 = Erc721 {
+                erc721: Default::default(),
+                token_names: Default::default(),
+                token_descriptions: Default::default(),
+                token_notes: Default::default(),
+                transfer_counts: Default::default(),
+                token_timestamps: Default::default(),
+                minters: Default::default(),
+                uri_overrides: Default::default(),
+                token_uris: Default::default(),
+                external_urls: Default::default(),
+                animation_urls: Default::default(),
+                artist_signatures: Default::default(),
+                rerolled: Default::default(),
+                token_palettes: Default::default(),
+                palettes: Default::default(),
+                seeds: Default::default(),
+                seed_sources: Default::default(),
+                token_editions: Default::default(),
+                editions: Default::default(),
+                frozen_metadata: Default::default(),
+                live_bitmap: Default::default(),
+                moderators: Default::default(),
+                top_holders: Default::default(),
+                all_tokens: Default::default(),
+                all_tokens_index: Default::default(),
+                owned_tokens: Default::default(),
+                owned_tokens_index: Default::default(),
+                contract_uri: Default::default(),
+                base_cid: Default::default(),
+                placeholder_uri: Default::default(),
+                rarity_table: Default::default(),
+                animation_base_url: Default::default(),
+                artist: Default::default(),
+                reroll_price: Default::default(),
+                palette_count: Default::default(),
+                script_hash: Default::default(),
+                uri_template: Default::default(),
+                charity_owed: Default::default(),
+                charity_owed_total: Default::default(),
+                script_chunks: Default::default(),
+                operators: Default::default(),
+                attributes: Default::default(),
+                attribute_keys: Default::default(),
+                attribute_tokens: Default::default(),
+                attribute_tokens_count: Default::default(),
+                attribute_tokens_index: Default::default(),
+                first_id,
+                token_id: first_id,
+                id_step,
+                minted_count: 0,
+                burned_count: 0,
+                holder_count: 0,
+                owner: Self::env().caller(),
+                name,
+                symbol,
+                base_uri,
+                max_supply,
+                mint_price,
+                royalty,
+                minter_royalty_share: 0,
+                max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+                charity: None,
+                charity_share: 0,
+                charity_balance: 0,
+                charity_donated: 0,
+                script_size: 0,
+                revealed,
+                metadata_frozen: false,
+                reveal_offset: 0,
+                uses_json_suffix,
+            };
+            if !placeholder_uri.is_empty() {
+                erc721.placeholder_uri.set(&placeholder_uri);
             }
+            Ok(erc721)
         }
 
         // #[ink(message)]
@@ -89,309 +1011,6006 @@ mod erc721 {
         // 少なくとも一つの#[ink(message)]属性を持つ関数が定義されている必要がある
         // コントラクトと対話するための関数定義に使用
 
-        // アカウントが持つトークンの数を返す
+        // コレクション名
         #[ink(message)]
-        pub fn balance_of(&self, owner: AccountId) -> u32 {
-            self.balance_of_or_zero(&owner)
+        pub fn name(&self) -> String {
+            self.name.clone()
         }
 
+        // コレクションのシンボル
         #[ink(message)]
-        pub fn token_uri(&self) -> String {
-            String::from(TOKEN_URI) + &ToString::to_string(&self.token_id)
+        pub fn symbol(&self) -> String {
+            self.symbol.clone()
         }
 
-        // トークンの所有者を取得する
+        // アカウントが持つトークンの数を返す
         #[ink(message)]
-        pub fn owner_of(&self, id: TokenId) -> Option<AccountId> {
-            self.token_owner.get(id)
+        pub fn balance_of(&self, owner: AccountId) -> u64 {
+            self.balance_of_or_zero(&owner)
         }
 
-        // 承認済みのアカウントIDを取得する
+        // 存在するトークンのURI。差し替えられていればそちらを返す
         #[ink(message)]
-        pub fn get_approved(&self, id: TokenId) -> Option<AccountId> {
-            self.token_approvals.get(id)
+        pub fn token_uri(&self, id: TokenId) -> Result<String, Error> {
+            if !self.exists(id) {
+                return Err(Error::TokenNotFound);
+            }
+            if let Some(uri) = self.uri_overrides.get(id) {
+                return Ok(uri);
+            }
+            Ok(self.metadata_uri(id))
         }
 
-        // 指定のアカウント間で全てApproveされているかどうか
-        #[ink(message)]
-        pub fn is_approved_for_all(&self, owner: AccountId, operator: AccountId) -> bool {
-            self.approved_for_all(owner, operator)
+        // モデレーターの差し替えを除いたURI
+        fn metadata_uri(&self, id: TokenId) -> String {
+            // 固定したトークンはあとから設定したプレースホルダーにも隠されない
+            if self.frozen_metadata.contains(id) {
+                if let Some(uri) = self.token_uris.get(id) {
+                    return uri;
+                }
+            }
+            // リビール前は個別のURIも隠す
+            if self.is_placeholder_active() {
+                return self.placeholder_uri.get().unwrap_or_default();
+            }
+            if let Some(uri) = self.token_uris.get(id) {
+                return uri;
+            }
+
+            // エディションはbase_uri/<エディションID>/<通し番号>
+            if let Some((edition_id, serial)) = self.token_editions.get(id) {
+                let mut buf = [0u8; MAX_ID_DIGITS];
+                let mut uri = String::with_capacity(self.base_uri.len() + 2 * MAX_ID_DIGITS + 6);
+                uri.push_str(&self.base_uri);
+                uri.push_str(format_id(TokenId::from(edition_id), &mut buf));
+                uri.push('/');
+                uri.push_str(format_id(TokenId::from(serial), &mut buf));
+                if self.uses_json_suffix {
+                    uri.push_str(".json");
+                }
+                return uri;
+            }
+
+            let mut buf = [0u8; MAX_ID_DIGITS];
+            let digits = format_id(self.metadata_id(id), &mut buf);
+
+            // テンプレートがあればCIDやベースURIより優先する
+            let template = self.uri_template.get().unwrap_or_default();
+            if !template.is_empty() {
+                let seed = self.seeds.get(id).unwrap_or_default();
+                return render_uri_template(&template, digits, &seed);
+            }
+
+            // CIDがあればipfs://<cid>/<id>.jsonにする
+            let cid = self.base_cid.get().unwrap_or_default();
+            if !cid.is_empty() {
+                let mut uri = String::with_capacity(7 + cid.len() + 1 + digits.len() + 5);
+                uri.push_str("ipfs://");
+                uri.push_str(&cid);
+                uri.push('/');
+                uri.push_str(digits);
+                uri.push_str(".json");
+                return uri;
+            }
+
+            // 確保は一回だけ
+            let mut uri = String::with_capacity(self.base_uri.len() + digits.len() + 5);
+            uri.push_str(&self.base_uri);
+            uri.push_str(digits);
+            if self.uses_json_suffix {
+                uri.push_str(".json");
+            }
+            uri
         }
 
-        // 指定のアカウントに対しての全承認をセットする
+        // 今のコレクションの設定。リビール済みならreveal_modeはImmediateになる
         #[ink(message)]
-        pub fn set_approval_for_all(&mut self, to: AccountId, approved: bool) -> Result<(), Error> {
-            self.approve_for_all(to, approved)?;
-            Ok(())
+        pub fn metadata_config(&self) -> MetadataConfig {
+            let reveal_mode = if self.revealed {
+                RevealMode::Immediate
+            } else {
+                RevealMode::Delayed {
+                    placeholder_uri: self.placeholder_uri.get().unwrap_or_default(),
+                }
+            };
+            MetadataConfig {
+                name: self.name.clone(),
+                symbol: self.symbol.clone(),
+                base_uri: self.base_uri.clone(),
+                uses_json_suffix: self.uses_json_suffix,
+                reveal_mode,
+                max_supply: self.supply_cap(),
+            }
         }
 
-        // 指定のアカウントがトークンに対しての操作をApproveする
-        #[ink(message)]
-        pub fn approve(&mut self, to: AccountId, id: TokenId) -> Result<(), Error> {
-            self.approve_for(&to, id)?;
-            Ok(())
+        // リビール前でプレースホルダーを返している
+        fn is_placeholder_active(&self) -> bool {
+            !self.revealed && !self.placeholder_uri.get().unwrap_or_default().is_empty()
         }
 
-        // トークンを移送
+        // トークンのメタデータを固定する。今のURIを個別のURIとして保存するので、
+        // あとからベースURIやCIDを変えても変わらない(モデレーターの差し替えはできる)
         #[ink(message)]
-        pub fn transfer(&mut self, destinaion: AccountId, id: TokenId) -> Result<(), Error> {
+        pub fn freeze_token_metadata(&mut self, id: TokenId) -> Result<(), Error> {
+            let owner = self.owner_or_err(id)?;
             let caller = self.env().caller();
-            self.transfer_token_from(&caller, &destinaion, id)?;
+            if caller != self.owner && caller != owner {
+                return Err(Error::NotOwner);
+            }
+            self.ensure_not_frozen(id)?;
+            if self.is_placeholder_active() {
+                return Err(Error::NotRevealed);
+            }
+
+            let uri = self.metadata_uri(id);
+            self.token_uris.insert(id, &uri);
+            self.frozen_metadata.insert(id, &());
+
+            self.env().emit_event(MetadataFrozen { id, uri });
             Ok(())
         }
 
-        // トークンを指定のアカウントからアカウントへ移送
         #[ink(message)]
-        pub fn transfer_from(
-            &mut self,
-            from: AccountId,
-            to: AccountId,
-            id: TokenId,
-        ) -> Result<(), Error> {
-            self.transfer_token_from(&from, &to, id)?;
+        pub fn is_metadata_frozen(&self, id: TokenId) -> bool {
+            self.metadata_frozen || self.frozen_metadata.contains(id)
+        }
+
+        fn ensure_not_frozen(&self, id: TokenId) -> Result<(), Error> {
+            if self.is_metadata_frozen(id) {
+                return Err(Error::MetadataFrozen);
+            }
             Ok(())
         }
 
-        // mint
+        // 全トークンのメタデータを固定する。ベースURI・CID・個別のURI・属性は二度と変えられない
+        // 最初の呼び出しで固定し、startからlimit個のTokenId(burn済みも数える)ごとにPermanentURIを出す
+        // limitはmax_batch_sizeまでに切り詰める。続きがあれば次のstartを返すので、Noneになるまで呼ぶ
         #[ink(message)]
-        pub fn mint(&mut self) -> Result<(), Error> {
-            let caller = self.env().caller();
-            let id = self.token_id;
-            self.add_token_to(&caller, id)?;
+        pub fn freeze_all_metadata(
+            &mut self,
+            start: TokenId,
+            limit: u32,
+        ) -> Result<Option<TokenId>, Error> {
+            self.ensure_owner()?;
+            if limit == 0 {
+                return Err(Error::InvalidParameter);
+            }
+            if !self.metadata_frozen {
+                if self.is_placeholder_active() {
+                    return Err(Error::NotRevealed);
+                }
+                self.metadata_frozen = true;
+            }
 
-            // イベント発火
-            self.env().emit_event(Transfer {
-                from: Some(AccountId::from([0x0; 32])),
-                to: Some(caller),
-                id,
-            });
+            let mut id = start.max(self.first_id);
+            for _ in 0..limit.min(self.max_batch_size) {
+                if id >= self.token_id {
+                    return Ok(None);
+                }
+                if self.exists(id) {
+                    let value = self.token_uri(id)?;
+                    self.env().emit_event(PermanentURI { value, id });
+                }
+                id = match id.checked_add(self.id_step) {
+                    Some(next) => next,
+                    None => return Ok(None),
+                };
+            }
+            Ok((id < self.token_id).then_some(id))
+        }
 
-            // インクリメント
-            self.token_id += 1;
+        #[ink(message)]
+        pub fn is_all_metadata_frozen(&self) -> bool {
+            self.metadata_frozen
+        }
 
+        fn ensure_metadata_mutable(&self) -> Result<(), Error> {
+            if self.metadata_frozen {
+                return Err(Error::MetadataFrozen);
+            }
             Ok(())
         }
 
-        // burn
+        // 今存在するトークンの数(mint数 - burn数)
         #[ink(message)]
-        pub fn burn(&mut self, id: TokenId) -> Result<(), Error> {
-            let caller = self.env().caller();
-            let Self {
-                token_owner,
-                owned_tokens_count,
-                ..
-            } = self;
-
-            let owner = token_owner.get(id).ok_or(Error::TokenNotFound)?;
-            if owner != caller {
-                return Err(Error::NotOwner);
-            }
+        pub fn total_supply(&self) -> u64 {
+            self.minted_count - self.burned_count
+        }
 
-            // トークン所持情報削除
-            let count = owned_tokens_count
-                .get(caller)
-                .map(|c| c - 1)
-                .ok_or(Error::CannotFetchValue)?;
-            owned_tokens_count.insert(caller, &count);
-            token_owner.remove(id);
+        // 通し番号からTokenIdを引く(0 <= index < total_supply)
+        // burnで順番は入れ替わるので、ページングの途中でburnされると飛ばしや重複がありうる
+        #[cfg(feature = "enumerable")]
+        #[ink(message)]
+        pub fn token_by_index(&self, index: u64) -> Option<TokenId> {
+            self.all_tokens.get(index)
+        }
 
-            // イベント発火
-            self.env().emit_event(Transfer {
-                from: Some(caller),
-                to: Some(AccountId::from([0x0; 32])),
-                id,
-            });
+        // 所有者のindex番目のトークン(0 <= index < balance_of(owner))
+        #[cfg(feature = "enumerable")]
+        #[ink(message)]
+        pub fn token_of_owner_by_index(&self, owner: AccountId, index: u64) -> Option<TokenId> {
+            self.owned_tokens.get((owner, index))
+        }
 
-            Ok(())
+        // 所有者のトークンをoffset番目からlimit個返す。limitがmax_batch_sizeを超えたらエラー
+        #[cfg(feature = "enumerable")]
+        #[ink(message)]
+        pub fn tokens_of_owner(
+            &self,
+            owner: AccountId,
+            offset: u32,
+            limit: u32,
+        ) -> Result<Vec<TokenId>, Error> {
+            self.ensure_batch_size(limit)?;
+            let balance = self.balance_of_or_zero(&owner);
+            let start = u64::from(offset).min(balance);
+            let end = start.saturating_add(u64::from(limit)).min(balance);
+            Ok((start..end)
+                .filter_map(|index| self.owned_tokens.get((owner, index)))
+                .collect())
         }
 
-        fn transfer_token_from(
-            &mut self,
-            from: &AccountId,
-            to: &AccountId,
+        // トークンが存在するか
+        #[ink(message)]
+        pub fn exists(&self, id: TokenId) -> bool {
+            Erc721Internal::exists(self, id)
+        }
+
+        // これまでにburnされたトークンの数
+        #[ink(message)]
+        pub fn total_burned(&self) -> u64 {
+            self.burned_count
+        }
+
+        // トークンを1つ以上持っているアカウントの数
+        #[ink(message)]
+        pub fn holder_count(&self) -> u64 {
+            self.holder_count
+        }
+
+        // 所有数の多いアカウント(降順、最大20件)
+        // ランキング外のアカウントとの比較はしないので、上位の所有数が減ったときは近似になる
+        #[ink(message)]
+        pub fn top_holders(&self) -> HolderRanking {
+            self.top_holders.get().unwrap_or_default()
+        }
+
+        // mintできるトークンの上限
+        #[ink(message)]
+        pub fn max_supply(&self) -> TokenId {
+            self.supply_cap()
+        }
+
+        // mint1回あたりの価格
+        #[ink(message)]
+        pub fn mint_price(&self) -> Balance {
+            self.mint_price
+        }
+
+        // ロイヤリティ(basis point)
+        #[cfg(feature = "royalties")]
+        #[ink(message)]
+        pub fn royalty(&self) -> u16 {
+            self.royalty
+        }
+
+        // ロイヤリティのうち最初のmint者に分ける割合(basis point)
+        #[cfg(feature = "royalties")]
+        #[ink(message)]
+        pub fn minter_royalty_share(&self) -> u16 {
+            self.minter_royalty_share
+        }
+
+        // 販売価格に対するロイヤリティの受け取り先と額
+        // 最初のmint者への分配が設定されていれば(アーティスト, mint者)の順に返す
+        #[cfg(feature = "royalties")]
+        #[ink(message)]
+        pub fn royalty_info(
+            &self,
             id: TokenId,
-        ) -> Result<(), Error> {
-            let caller = self.env().caller();
+            sale_price: Balance,
+        ) -> Result<Vec<(AccountId, Balance)>, Error> {
+            let minter = self.minters.get(id).ok_or(Error::TokenNotFound)?;
+            let artist = self.royalty_receiver();
+
+            let royalty = sale_price.saturating_mul(Balance::from(self.royalty))
+                / Balance::from(ROYALTY_DENOMINATOR);
+            let minter_part = royalty.saturating_mul(Balance::from(self.minter_royalty_share))
+                / Balance::from(ROYALTY_DENOMINATOR);
+
+            if minter_part == 0 || minter == artist {
+                return Ok(ink::prelude::vec![(artist, royalty)]);
+            }
+            Ok(ink::prelude::vec![
+                (artist, royalty - minter_part),
+                (minter, minter_part)
+            ])
+        }
+
+        // ロイヤリティのうち最初のmint者に分ける割合を変更する
+        #[cfg(feature = "royalties")]
+        #[ink(message)]
+        pub fn set_minter_royalty_share(&mut self, share: u16) -> Result<(), Error> {
+            self.ensure_owner()?;
+            if share > ROYALTY_DENOMINATOR {
+                return Err(Error::InvalidParameter);
+            }
+            self.minter_royalty_share = share;
+            Ok(())
+        }
 
+        // 1回のメッセージで扱える件数の上限
+        #[ink(message)]
+        pub fn max_batch_size(&self) -> u32 {
+            self.max_batch_size
+        }
+
+        // トークンの所有者を取得する
+        #[ink(message)]
+        pub fn owner_of(&self, id: TokenId) -> Option<AccountId> {
+            self.erc721.token_owner.get(id)
+        }
+
+        // トークンの所有者を取得する。トークンがなければTokenNotFound
+        #[ink(message)]
+        pub fn checked_owner_of(&self, id: TokenId) -> Result<AccountId, Error> {
+            self.owner_or_err(id)
+        }
+
+        // 承認済みのアカウントIDを取得する
+        // トークンがなければTokenNotFound、Approveがなければ Ok(None)
+        #[ink(message)]
+        pub fn get_approved(&self, id: TokenId) -> Result<Option<AccountId>, Error> {
             if !self.exists(id) {
                 return Err(Error::TokenNotFound);
             }
+            Ok(self.erc721.token_approvals.get(id))
+        }
+
+        // 指定のアカウント間で全てApproveされているかどうか
+        #[ink(message)]
+        pub fn is_approved_for_all(&self, owner: AccountId, operator: AccountId) -> bool {
+            self.approved_for_all(owner, operator)
+        }
+
+        // 指定のアカウントがトークンを操作できるか(所有者・Approve済み・全承認)
+        #[ink(message)]
+        pub fn is_approved_or_owner(&self, account: AccountId, id: TokenId) -> bool {
+            self.owner_or_err(id)
+                .and_then(|owner| self.approved_or_owner(account, owner, id))
+                .is_ok()
+        }
+
+        // 全承認しているオペレーターの一覧
+        #[ink(message)]
+        pub fn operators_of(&self, owner: AccountId) -> Vec<AccountId> {
+            self.operators.get(owner).unwrap_or_default()
+        }
+
+        // 指定のアカウントに対しての全承認をセットする
+        #[ink(message)]
+        pub fn set_approval_for_all(&mut self, to: AccountId, approved: bool) -> Result<(), Error> {
+            self.approve_for_all(to, approved)?;
+            Ok(())
+        }
+
+        // 指定のアカウントがトークンに対しての操作をApproveする
+        // valid_untilを過ぎて取り込まれた呼び出しは失敗する
+        #[ink(message)]
+        pub fn approve(
+            &mut self,
+            to: AccountId,
+            id: TokenId,
+            valid_until: Option<Timestamp>,
+        ) -> Result<(), Error> {
+            self.ensure_not_expired(valid_until)?;
+            self.approve_for(&to, id)?;
+            Ok(())
+        }
 
-            if !self.approved_or_owner(Some(caller), id) {
+        // トークンのApproveを取り消す。所有者かオペレーターだけが呼べる
+        // 取り消したことはゼロアドレスへのApprovalイベントで通知する
+        #[ink(message)]
+        pub fn revoke_approval(&mut self, id: TokenId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            let owner = self.owner_or_err(id)?;
+            if !(owner == caller || self.approved_for_all(owner, caller)) {
                 return Err(Error::NotApproved);
             }
 
-            // Approval情報をクリア
             self.clear_approval(id);
-            // トークンの所有情報を削除
-            self.remove_token_from(from, id)?;
-            // トークンの所有情報を追加
-            self.add_token_to(to, id)?;
 
             // イベント発火
-            self.env().emit_event(Transfer {
-                from: Some(*from),
-                to: Some(*to),
+            self.env().emit_event(Approval {
+                from: caller,
+                to: AccountId::from(internal::ZERO_ACCOUNT),
                 id,
             });
 
             Ok(())
         }
 
-        fn add_token_to(&mut self, to: &AccountId, id: TokenId) -> Result<(), Error> {
-            let Self {
-                token_owner,
-                owned_tokens_count,
-                ..
-            } = self;
+        // トークンを移送
+        // valid_untilを過ぎて取り込まれた呼び出しは失敗する
+        #[ink(message)]
+        pub fn transfer(
+            &mut self,
+            destinaion: AccountId,
+            id: TokenId,
+            memo: Option<String>,
+            valid_until: Option<Timestamp>,
+        ) -> Result<(), Error> {
+            self.ensure_not_expired(valid_until)?;
+            let caller = self.env().caller();
+            self.transfer_token_from(&caller, &destinaion, id, memo)?;
+            Ok(())
+        }
 
-            // 既にトークン誰か持ってる
-            if token_owner.contains(id) {
-                return Err(Error::TokenExists);
-            }
+        // トークンを指定のアカウントからアカウントへ移送
+        #[ink(message)]
+        pub fn transfer_from(
+            &mut self,
+            from: AccountId,
+            to: AccountId,
+            id: TokenId,
+            memo: Option<String>,
+            valid_until: Option<Timestamp>,
+        ) -> Result<(), Error> {
+            self.ensure_not_expired(valid_until)?;
+            self.transfer_token_from(&from, &to, id, memo)?;
+            Ok(())
+        }
 
-            // ゼロアドレス
-            if *to == AccountId::from([0x0; 32]) {
-                return Err(Error::NotAllowed);
+        // トークンを転送し、toがコントラクトならdataをつけて受け取りフックを呼ぶ
+        #[ink(message)]
+        pub fn safe_transfer_from_with_data(
+            &mut self,
+            from: AccountId,
+            to: AccountId,
+            id: TokenId,
+            data: Vec<u8>,
+        ) -> Result<(), Error> {
+            self.transfer_token_from(&from, &to, id, None)?;
+            if self.env().is_contract(&to) {
+                self.call_on_received(from, to, id, data)?;
             }
+            Ok(())
+        }
 
-            let count = owned_tokens_count.get(to).map(|c| c + 1).unwrap_or(1);
+        // 呼び出しもとがfromからtoへトークンを転送できるか(状態は変えない)
+        // 転送したときに返るエラーをそのまま返す
+        #[ink(message)]
+        pub fn can_transfer(
+            &self,
+            from: AccountId,
+            to: AccountId,
+            id: TokenId,
+        ) -> Result<(), Error> {
+            let caller = self.env().caller();
+            self.check_transfer(caller, &from, &to, id)
+        }
 
-            owned_tokens_count.insert(to, &count);
-            token_owner.insert(id, to);
+        // accountへcount個mintできるか(状態は変えない)
+        // 代金は1個あたりmint_priceが必要
+        #[ink(message)]
+        pub fn can_mint(&self, account: AccountId, count: u32) -> Result<(), Error> {
+            self.check_mint(&account, count)
+        }
 
-            Ok(())
+        // mint
+        // toがNoneなら呼び出しもとへ、連番でquantity個mintする。代金は呼び出しもとが払う
+        // entropyを渡すとシードの計算に混ぜる。mintしたTokenIdを返す
+        #[ink(message, payable)]
+        pub fn mint(
+            &mut self,
+            to: Option<AccountId>,
+            quantity: u32,
+            entropy: Option<Vec<u8>>,
+        ) -> Result<Vec<TokenId>, Error> {
+            let to = to.unwrap_or_else(|| self.env().caller());
+            let entropy = entropy.unwrap_or_default();
+            if entropy.len() > MAX_ENTROPY_LEN {
+                return Err(Error::InvalidParameter);
+            }
+            self.paid_mint(&to, quantity, &entropy)
         }
 
-        fn clear_approval(&self, id: TokenId) {
-            self.token_approvals.remove(id);
+        // 指定のアカウントへmintする。代金は呼び出しもとが払う
+        #[ink(message, payable)]
+        pub fn mint_to(&mut self, to: AccountId) -> Result<TokenId, Error> {
+            let ids = self.paid_mint(&to, 1, &[])?;
+            Ok(ids[0])
         }
 
-        fn remove_token_from(&mut self, from: &AccountId, id: TokenId) -> Result<(), Error> {
-            // 構造体からフィールドを取り出す
-            let Self {
-                token_owner,
-                owned_tokens_count,
-                ..
-            } = self;
+        // 連番でn個mintする。代金はn個分ちょうど払う
+        // gasが足りなければ途中で止めて残りの代金を返す(BatchMintTruncatedで続きを知らせる)
+        #[ink(message, payable)]
+        pub fn mint_many(&mut self, n: u32) -> Result<Vec<TokenId>, Error> {
+            let caller = self.env().caller();
+            self.paid_mint(&caller, n, &[])
+        }
 
-            // トークンがない
-            if !token_owner.contains(id) {
-                return Err(Error::TokenNotFound);
-            }
+        // オーナーが指定のアカウントへ連番でn個mintする(代金なし)
+        #[ink(message)]
+        pub fn mint_many_to(&mut self, to: AccountId, n: u32) -> Result<Vec<TokenId>, Error> {
+            self.ensure_owner()?;
+            let count = self.affordable_mints(n);
+            let ids = self.mint_batch(&to, count, &[])?;
+            self.emit_batch_mint_truncated(&to, n, count);
+            Ok(ids)
+        }
 
-            let count = owned_tokens_count
-                .get(from) // トークンの所有数
-                .map(|c| c - 1) // 1減らす
-                .ok_or(Error::CannotFetchValue)?; // 見つからなかったらエラー返す
+        // オーナーが同じ作品のエディションをsize枚、自分へmintする(代金なし)
+        // 通し番号は1から振り、メタデータはbase_uri/<edition_id>/<通し番号>になる
+        #[ink(message)]
+        pub fn mint_edition(&mut self, edition_id: u32, size: u32) -> Result<Vec<TokenId>, Error> {
+            self.ensure_owner()?;
+            if size == 0 {
+                return Err(Error::InvalidParameter);
+            }
+            if self.editions.contains(edition_id) {
+                return Err(Error::EditionExists);
+            }
 
-            // トークン所有数を更新
-            owned_tokens_count.insert(from, &count);
-            // トークン所有者を削除する
-            token_owner.remove(id);
+            let caller = self.env().caller();
+            let ids = self.mint_batch(&caller, size, &[])?;
+            for (serial, &id) in (1u32..).zip(&ids) {
+                self.token_editions.insert(id, &(edition_id, serial));
+            }
+            let first_id = ids[0];
+            self.editions.insert(edition_id, &(first_id, size));
+            self.env().emit_event(EditionMinted {
+                edition_id,
+                first_id,
+                size,
+            });
+            Ok(ids)
+        }
 
-            Ok(())
+        // トークンの(エディションID, 通し番号)。エディションでなければNone
+        #[ink(message)]
+        pub fn edition_of(&self, id: TokenId) -> Option<(u32, u32)> {
+            self.token_editions.get(id)
         }
 
-        // 指定のアドレスが所有者　または　指定のトークンに対してのApprovalがある　または　allでApprovalされてる
-        fn approved_or_owner(&self, from: Option<AccountId>, id: TokenId) -> bool {
-            let owner = self.owner_of(id);
-            from != Some(AccountId::from([0x0; 32]))
-                && (from == owner
-                    || from == self.token_approvals.get(id)
-                    || self.approved_for_all(
-                        owner.expect("Error with AccountId"),
-                        from.expect("Error with AccountId"),
-                    ))
+        // エディションの(最初のTokenId, 枚数)。TokenIdは最初のTokenIdからid_stepずつ並ぶ
+        #[ink(message)]
+        pub fn edition(&self, edition_id: u32) -> Option<(TokenId, u32)> {
+            self.editions.get(edition_id)
         }
 
-        fn exists(&self, id: TokenId) -> bool {
-            self.token_owner.contains(id)
+        // burn
+        #[ink(message)]
+        // 所有者のほか、Approveされたアカウントやオペレーターもburnできる
+        pub fn burn(&mut self, id: TokenId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            let owner = self.owner_or_err(id)?;
+            self.approved_or_owner(caller, owner, id)?;
+            self.before_token_transfer(Some(&owner), None, id)?;
+
+            // トークン所持情報削除
+            self.remove_token_from(&owner, id)?;
+            self.finish_burn(&owner, caller, id);
+
+            Ok(())
         }
 
-        fn approve_for(&mut self, to: &AccountId, id: TokenId) -> Result<(), Error> {
-            // 呼び出しもと
+        // まとめてburnする。先に全部確認するので一部だけburnされることはない
+        // 所有数の更新は所有者ごとに一回だけ
+        #[ink(message)]
+        pub fn burn_batch(&mut self, ids: Vec<TokenId>) -> Result<(), Error> {
+            self.ensure_batch_size(ids.len() as u32)?;
             let caller = self.env().caller();
-            // トークン所有者
-            let owner = self.owner_of(id);
 
-            // 呼び出しもとと所有者が同じまたは、既にApproveされてる
-            if !(owner == Some(caller)
-                || self.approved_for_all(owner.expect("Error with AccountId"), caller))
-            {
-                return Err(Error::NotAllowed);
+            // 所有者ごとにまとめる
+            let mut groups: Vec<(AccountId, Vec<TokenId>)> = Vec::new();
+            for (i, &id) in ids.iter().enumerate() {
+                if ids[..i].contains(&id) {
+                    return Err(Error::InvalidParameter);
+                }
+                let owner = self.owner_or_err(id)?;
+                self.approved_or_owner(caller, owner, id)?;
+                self.before_token_transfer(Some(&owner), None, id)?;
+                match groups.iter_mut().find(|(o, _)| *o == owner) {
+                    Some((_, group)) => group.push(id),
+                    None => groups.push((owner, ink::prelude::vec![id])),
+                }
             }
 
-            // 0アドレス
-            if *to == AccountId::from([0x0; 32]) {
-                return Err(Error::NotAllowed);
+            for (owner, group) in &groups {
+                self.remove_tokens_from(owner, group)?;
+                for &id in group {
+                    self.finish_burn(owner, caller, id);
+                }
             }
 
-            // ストレージに追加
-            if self.token_approvals.contains(id) {
-                return Err(Error::CannotInsert);
-            } else {
-                self.token_approvals.insert(id, to);
+            Ok(())
+        }
+
+        // コントラクトに資金を追加する
+        // payableでないメッセージへの送金はink!がメッセージの呼び出し前に弾く
+        #[ink(message, payable)]
+        pub fn fund(&mut self) -> Result<(), Error> {
+            let amount = self.env().transferred_value();
+            if amount == 0 {
+                return Err(Error::InsufficientPayment);
             }
 
             // イベント発火
-            self.env().emit_event(Approval {
-                from: caller,
-                to: *to,
-                id,
+            self.env().emit_event(Funded {
+                from: self.env().caller(),
+                amount,
             });
 
             Ok(())
         }
 
-        fn approve_for_all(&mut self, to: AccountId, approved: bool) -> Result<(), Error> {
-            let caller = self.env().caller();
-            if to == caller {
-                return Err(Error::NotAllowed);
+        // mintの売上を引き出す
+        #[ink(message)]
+        pub fn withdraw(&mut self, to: AccountId, amount: Balance) -> Result<(), Error> {
+            self.ensure_owner()?;
+
+            // 寄付分は引き出せない
+            let available = self
+                .env()
+                .balance()
+                .saturating_sub(self.charity_balance)
+                .saturating_sub(self.charity_owed_total.get().unwrap_or_default());
+            if amount > available {
+                return Err(Error::InsufficientBalance);
+            }
+
+            self.env()
+                .transfer(to, amount)
+                .map_err(|_| Error::TransferFailed)
+        }
+
+        // 寄付先と割合を設定する。Noneで寄付をやめる
+        // 前の寄付先に送っていない分は送金に失敗しても変更できるよう、pay_charity_owedで別に送る
+        #[ink(message)]
+        pub fn set_charity(&mut self, charity: Option<AccountId>, share: u16) -> Result<(), Error> {
+            self.ensure_owner()?;
+            if share > ROYALTY_DENOMINATOR || (charity.is_none() && share != 0) {
+                return Err(Error::InvalidParameter);
             }
+            if let Some(to) = charity {
+                internal::ensure_not_zero(&to)?;
+            }
+
+            if let (Some(old), amount @ 1..) = (self.charity, self.charity_balance) {
+                let owed = self.charity_owed.get(old).unwrap_or_default();
+                self.charity_owed.insert(old, &owed.saturating_add(amount));
+                let total = self.charity_owed_total.get().unwrap_or_default();
+                self.charity_owed_total.set(&total.saturating_add(amount));
+                self.charity_balance = 0;
+            }
+            self.charity = charity;
+            self.charity_share = share;
 
             // イベント発火
-            self.env().emit_event(ApprovalForAll {
-                owner: caller,
-                operator: to,
-                approved,
-            });
+            self.env().emit_event(CharityChanged { charity, share });
 
-            if approved {
-                self.operator_approvals.insert((&caller, &to), &());
-            } else {
-                self.operator_approvals.remove((&caller, &to));
+            Ok(())
+        }
+
+        // 貯まっている寄付分を寄付先に送金する。誰でも呼べる
+        #[ink(message)]
+        pub fn pay_charity(&mut self) -> Result<(), Error> {
+            let (Some(charity), amount) = (self.charity, self.charity_balance) else {
+                return Ok(());
+            };
+            if amount == 0 {
+                return Ok(());
+            }
+
+            self.env()
+                .transfer(charity, amount)
+                .map_err(|_| Error::TransferFailed)?;
+            self.charity_balance = 0;
+            self.charity_donated = self.charity_donated.saturating_add(amount);
+
+            // イベント発火
+            self.env().emit_event(CharityPaid { charity, amount });
+
+            Ok(())
+        }
+
+        // 寄付先を変える前の分を前の寄付先に送金する。誰でも呼べる
+        #[ink(message)]
+        pub fn pay_charity_owed(&mut self, charity: AccountId) -> Result<(), Error> {
+            let amount = self.charity_owed.get(charity).unwrap_or_default();
+            if amount == 0 {
+                return Ok(());
             }
 
+            self.env()
+                .transfer(charity, amount)
+                .map_err(|_| Error::TransferFailed)?;
+            self.charity_owed.remove(charity);
+            let total = self.charity_owed_total.get().unwrap_or_default();
+            self.charity_owed_total.set(&total.saturating_sub(amount));
+            self.charity_donated = self.charity_donated.saturating_add(amount);
+
+            // イベント発火
+            self.env().emit_event(CharityPaid { charity, amount });
+
             Ok(())
         }
 
-        fn balance_of_or_zero(&self, of: &AccountId) -> u32 {
-            self.owned_tokens_count.get(of).unwrap_or(0)
+        // 前の寄付先にまだ送っていない額
+        #[ink(message)]
+        pub fn charity_owed(&self, charity: AccountId) -> Balance {
+            self.charity_owed.get(charity).unwrap_or_default()
         }
 
-        fn approved_for_all(&self, owner: AccountId, operator: AccountId) -> bool {
-            self.operator_approvals.contains((&owner, &operator))
+        // 寄付先
+        #[ink(message)]
+        pub fn charity(&self) -> Option<AccountId> {
+            self.charity
         }
-    }
 
-    #[cfg(test)]
-    mod tests {
-        use super::*;
+        // 寄付する割合(basis point)
+        #[ink(message)]
+        pub fn charity_share(&self) -> u16 {
+            self.charity_share
+        }
 
-        #[ink::test]
-        fn mint_works() {
-            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
-            let mut erc721 = Erc721::new();
+        // 寄付先にまだ送っていない額
+        #[ink(message)]
+        pub fn charity_balance(&self) -> Balance {
+            self.charity_balance
+        }
 
-            // まだトークンがmintされていないので所有者はいない
-            assert_eq!(erc721.owner_of(1), None);
-            // デフォルトユーザーでまだmintしていないのでトークンをもっていない
-            assert_eq!(erc721.balance_of(accounts.alice), 0);
-            // mint成功するはず
-            assert_eq!(erc721.mint(), Ok(()));
-            // mintしたのでトークンを所有しているはず
-            assert_eq!(erc721.balance_of(accounts.alice), 1);
+        // これまでに寄付した合計
+        #[ink(message)]
+        pub fn charity_donated(&self) -> Balance {
+            self.charity_donated
+        }
+
+        // トークンのアーティストに投げ銭する。受け取った額はそのまま送金する
+        #[ink(message, payable)]
+        pub fn tip(&mut self, id: TokenId) -> Result<(), Error> {
+            // 存在しないトークン
+            if !self.exists(id) {
+                return Err(Error::TokenNotFound);
+            }
+
+            let amount = self.env().transferred_value();
+            if amount == 0 {
+                return Err(Error::InsufficientPayment);
+            }
+
+            let artist = self.royalty_receiver();
+            self.env()
+                .transfer(artist, amount)
+                .map_err(|_| Error::TransferFailed)?;
+
+            // イベント発火
+            self.env().emit_event(Tipped {
+                id,
+                from: self.env().caller(),
+                to: artist,
+                amount,
+            });
+
+            Ok(())
+        }
+
+        // startからlen個のトークンが存在するかどうか
+        #[ink(message)]
+        pub fn exists_range(&self, start: TokenId, len: u32) -> Result<Vec<bool>, Error> {
+            self.ensure_batch_size(len)?;
+
+            let mut result = Vec::with_capacity(len as usize);
+            let mut word_index = None;
+            let mut word = [0u64; 4];
+            for id in start..start.saturating_add(TokenId::from(len)) {
+                // 同じワードは読み直さない
+                let (index, bit) = (id / 256, id % 256);
+                if word_index != Some(index) {
+                    word = self.live_bitmap.get(index).unwrap_or_default();
+                    word_index = Some(index);
+                }
+                result.push(word[(bit / 64) as usize] & (1 << (bit % 64)) != 0);
+            }
+            Ok(result)
+        }
+
+        // トークンが転送された回数
+        #[ink(message)]
+        pub fn transfer_count(&self, id: TokenId) -> u32 {
+            self.transfer_counts.get(id).unwrap_or(0)
+        }
+
+        // 最初にmintしたアカウント
+        #[ink(message)]
+        pub fn minter_of(&self, id: TokenId) -> Option<AccountId> {
+            self.minters.get(id)
+        }
+
+        // mintされた時刻
+        #[ink(message)]
+        pub fn minted_at(&self, id: TokenId) -> Option<Timestamp> {
+            self.token_timestamps
+                .get(id)
+                .map(|(minted_at, _)| minted_at)
+        }
+
+        // p5.jsのスケッチで同じ絵を描き直すためのシード
+        #[ink(message)]
+        pub fn seed_of(&self, id: TokenId) -> Option<[u8; 32]> {
+            self.seeds.get(id)
+        }
+
+        // シードの材料。Blake2x256((アカウント, ブロック番号, TokenId, エントロピー))がシードになる
+        // randomnessフィーチャーではさらにノードの乱数を通す。引き直したシードはこれとは合わない
+        #[ink(message)]
+        pub fn seed_source(&self, id: TokenId) -> Option<SeedSource> {
+            self.seed_sources.get(id)
+        }
+
+        // 所有者がトークンごとに一回だけシードを引き直す。レアリティ表の属性も選び直す
+        // 代金はreroll_priceちょうど
+        #[ink(message, payable)]
+        pub fn reroll_seed(&mut self, id: TokenId) -> Result<[u8; 32], Error> {
+            self.ensure_token_owner(id)?;
+            self.ensure_not_frozen(id)?;
+            if self.rerolled.contains(id) {
+                return Err(Error::AlreadyRerolled);
+            }
+            let price = self.reroll_price();
+            let paid = self.env().transferred_value();
+            if paid < price {
+                return Err(Error::InsufficientPayment);
+            }
+            if paid > price {
+                return Err(Error::UnexpectedValue);
+            }
+
+            // 同じブロックでmintしてもmint時と同じシードにならないよう、元のシードも混ぜる
+            let old = self.seeds.get(id).unwrap_or_default();
+            let mut subject = [0u8; 32];
+            ink::env::hash_encoded::<ink::env::hash::Blake2x256, _>(
+                &(old, self.env().caller(), self.env().block_number(), id),
+                &mut subject,
+            );
+            let seed = self.random_seed(subject)?;
+
+            self.assign_traits(id, &seed, &self.rarity_table())?;
+            self.seeds.insert(id, &seed);
+            self.rerolled.insert(id, &());
+            self.record_revenue(paid);
+            self.env().emit_event(SeedRerolled { id, seed });
+            self.env().emit_event(MetadataUpdate { id });
+            Ok(seed)
+        }
+
+        #[ink(message)]
+        pub fn is_rerolled(&self, id: TokenId) -> bool {
+            self.rerolled.contains(id)
+        }
+
+        // シードの引き直しの代金を設定する
+        #[ink(message)]
+        pub fn set_reroll_price(&mut self, price: Balance) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.reroll_price.set(&price);
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn reroll_price(&self) -> Balance {
+            self.reroll_price.get().unwrap_or_default()
+        }
+
+        // 最後に転送された時刻(転送されていなければmintの時刻)
+        #[ink(message)]
+        pub fn last_transfer_at(&self, id: TokenId) -> Option<Timestamp> {
+            self.token_timestamps.get(id).map(|(_, last)| last)
+        }
+
+        // モデレーター権限を付与・剥奪する
+        #[ink(message)]
+        pub fn set_moderator(&mut self, account: AccountId, enabled: bool) -> Result<(), Error> {
+            self.ensure_owner()?;
+            if enabled {
+                self.moderators.insert(account, &());
+            } else {
+                self.moderators.remove(account);
+            }
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn is_moderator(&self, account: AccountId) -> bool {
+            self.moderators.contains(account)
+        }
+
+        // トークンのURIを差し替える(削除要請の告知ページなど)
+        #[ink(message)]
+        pub fn override_token_uri(&mut self, id: TokenId, uri: String) -> Result<(), Error> {
+            self.set_uri_override(id, Some(uri))
+        }
+
+        // URIの差し替えを解除する
+        #[ink(message)]
+        pub fn clear_token_uri_override(&mut self, id: TokenId) -> Result<(), Error> {
+            self.set_uri_override(id, None)
+        }
+
+        // トークンのURIを個別に指定する(1点物など)。オーナーかモデレーターだけができる
+        #[ink(message)]
+        pub fn set_token_uri(&mut self, id: TokenId, uri: String) -> Result<(), Error> {
+            if uri.is_empty() {
+                return Err(Error::InvalidParameter);
+            }
+            self.set_pinned_uri(id, Some(uri))
+        }
+
+        // メタデータのexternal_urlを設定する。オーナーかモデレーターだけができる。空文字なら消す
+        #[ink(message)]
+        pub fn set_external_url(&mut self, id: TokenId, url: String) -> Result<(), Error> {
+            self.ensure_metadata_admin(id)?;
+            if url.len() > MAX_URL_LEN {
+                return Err(Error::TextTooLong);
+            }
+            if url.is_empty() {
+                self.external_urls.remove(id);
+            } else {
+                self.external_urls.insert(id, &url);
+            }
+            self.env().emit_event(MetadataUpdate { id });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn external_url(&self, id: TokenId) -> Option<String> {
+            self.external_urls.get(id)
+        }
+
+        // トークンのanimation_urlを個別に設定する。オーナーかモデレーターだけができる。空文字なら既定値に戻す
+        #[ink(message)]
+        pub fn set_animation_url(&mut self, id: TokenId, url: String) -> Result<(), Error> {
+            self.ensure_metadata_admin(id)?;
+            if url.len() > MAX_URL_LEN {
+                return Err(Error::TextTooLong);
+            }
+            if url.is_empty() {
+                self.animation_urls.remove(id);
+            } else {
+                self.animation_urls.insert(id, &url);
+            }
+            self.env().emit_event(MetadataUpdate { id });
+            Ok(())
+        }
+
+        // animation_urlの既定値のベースを設定する。空文字なら既定値なし
+        #[ink(message)]
+        pub fn set_animation_base_url(&mut self, url: String) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.ensure_metadata_mutable()?;
+            if url.len() > MAX_URL_LEN {
+                return Err(Error::TextTooLong);
+            }
+            self.animation_base_url.set(&url);
+            self.emit_batch_metadata_update();
+            Ok(())
+        }
+
+        // トークンのanimation_url。個別の設定がなければanimation_base_url + TokenId
+        #[ink(message)]
+        pub fn animation_url(&self, id: TokenId) -> Option<String> {
+            if let Some(url) = self.animation_urls.get(id) {
+                return Some(url);
+            }
+            let base = self.animation_base_url.get().unwrap_or_default();
+            if base.is_empty() || !self.exists(id) {
+                return None;
+            }
+            let mut buf = [0u8; MAX_ID_DIGITS];
+            let digits = format_id(id, &mut buf);
+            let mut url = String::with_capacity(base.len() + digits.len());
+            url.push_str(&base);
+            url.push_str(digits);
+            Some(url)
+        }
+
+        // トークンに署名できるアーティストを設定する。Noneなら解除
+        #[ink(message)]
+        pub fn set_artist(&mut self, artist: Option<AccountId>) -> Result<(), Error> {
+            self.ensure_owner()?;
+            match artist {
+                Some(account) => self.artist.set(&account),
+                None => self.artist.set(&AccountId::from(internal::ZERO_ACCOUNT)),
+            }
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn artist(&self) -> Option<AccountId> {
+            self.artist
+                .get()
+                .filter(|account| !internal::is_zero(account))
+        }
+
+        // アーティストがトークンに署名を付ける。付け直すと上書きする
+        #[ink(message)]
+        pub fn sign_token(&mut self, id: TokenId, signature: Vec<u8>) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if self.artist() != Some(caller) {
+                return Err(Error::NotArtist);
+            }
+            self.owner_or_err(id)?;
+            if signature.is_empty() || signature.len() > MAX_SIGNATURE_LEN {
+                return Err(Error::InvalidSignature);
+            }
+            self.artist_signatures.insert(id, &signature);
+            self.env().emit_event(TokenSigned {
+                id,
+                artist: caller,
+                signature,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn artist_signature(&self, id: TokenId) -> Option<Vec<u8>> {
+            self.artist_signatures.get(id)
+        }
+
+        // 個別に指定したURIを解除してベースURI + TokenIdに戻す
+        #[ink(message)]
+        pub fn clear_token_uri(&mut self, id: TokenId) -> Result<(), Error> {
+            self.set_pinned_uri(id, None)
+        }
+
+        // 差し替えられたURI
+        #[ink(message)]
+        pub fn token_uri_override(&self, id: TokenId) -> Option<String> {
+            self.uri_overrides.get(id)
+        }
+
+        // URIが差し替えられているか
+        #[ink(message)]
+        pub fn is_token_uri_overridden(&self, id: TokenId) -> bool {
+            self.uri_overrides.contains(id)
+        }
+
+        // トークンにメモを追加する。所有者だけが書ける
+        #[ink(message)]
+        pub fn add_note(&mut self, id: TokenId, note: String) -> Result<(), Error> {
+            let owner = self.owner_or_err(id)?;
+            if owner != self.env().caller() {
+                return Err(Error::NotOwner);
+            }
+            if note.is_empty() {
+                return Err(Error::InvalidParameter);
+            }
+            if note.len() > MAX_NOTE_LEN {
+                return Err(Error::NoteTooLong);
+            }
+
+            let mut notes = self.notes_of(id);
+            if notes.len() >= MAX_NOTES {
+                return Err(Error::TooManyNotes);
+            }
+            notes.push(note);
+            self.token_notes.insert(id, &notes);
+
+            Ok(())
+        }
+
+        // トークンに名前を付ける。所有者だけが書ける。空文字なら消す
+        #[ink(message)]
+        pub fn set_token_name(&mut self, id: TokenId, name: String) -> Result<(), Error> {
+            self.ensure_token_owner(id)?;
+            if name.len() > MAX_TOKEN_NAME_LEN {
+                return Err(Error::TextTooLong);
+            }
+            if name.is_empty() {
+                self.token_names.remove(id);
+            } else {
+                self.token_names.insert(id, &name);
+            }
+            self.env().emit_event(MetadataUpdate { id });
+            Ok(())
+        }
+
+        // トークンに説明を付ける。所有者だけが書ける。空文字なら消す
+        #[ink(message)]
+        pub fn set_token_description(&mut self, id: TokenId, text: String) -> Result<(), Error> {
+            self.ensure_token_owner(id)?;
+            if text.len() > MAX_TOKEN_DESCRIPTION_LEN {
+                return Err(Error::TextTooLong);
+            }
+            if text.is_empty() {
+                self.token_descriptions.remove(id);
+            } else {
+                self.token_descriptions.insert(id, &text);
+            }
+            self.env().emit_event(MetadataUpdate { id });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn token_name(&self, id: TokenId) -> Option<String> {
+            self.token_names.get(id)
+        }
+
+        #[ink(message)]
+        pub fn token_description(&self, id: TokenId) -> Option<String> {
+            self.token_descriptions.get(id)
+        }
+
+        // トークンに残されたメモ(古い順)
+        #[ink(message)]
+        pub fn notes_of(&self, id: TokenId) -> Vec<String> {
+            self.token_notes.get(id).unwrap_or_default()
+        }
+
+        // トークンの属性を設定する。コントラクトのオーナーだけが書ける
+        #[ink(message)]
+        pub fn set_attribute(
+            &mut self,
+            id: TokenId,
+            key: String,
+            value: String,
+        ) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.owner_or_err(id)?;
+            self.ensure_not_frozen(id)?;
+            self.write_attribute(id, key, value)?;
+            self.env().emit_event(MetadataUpdate { id });
+            Ok(())
+        }
+
+        // パレットを登録して番号を返す。これ以降にmintしたトークンから選ばれる
+        #[ink(message)]
+        pub fn add_palette(&mut self, name: String, colors: Vec<[u8; 3]>) -> Result<u32, Error> {
+            self.ensure_owner()?;
+            if name.is_empty() {
+                return Err(Error::InvalidParameter);
+            }
+            if name.len() > MAX_PALETTE_NAME_LEN {
+                return Err(Error::TextTooLong);
+            }
+            if colors.is_empty() || colors.len() > MAX_PALETTE_COLORS {
+                return Err(Error::InvalidParameter);
+            }
+
+            let id = self.palette_count();
+            let count = id.checked_add(1).ok_or(Error::Overflow)?;
+            self.palettes.insert(id, &(name.clone(), colors));
+            self.palette_count.set(&count);
+            self.env().emit_event(PaletteAdded { id, name });
+            Ok(id)
+        }
+
+        #[ink(message)]
+        pub fn palette(&self, id: u32) -> Option<Palette> {
+            self.palettes.get(id)
+        }
+
+        #[ink(message)]
+        pub fn palette_count(&self) -> u32 {
+            self.palette_count.get().unwrap_or(0)
+        }
+
+        // トークンに選ばれたパレットの番号。mintのときにパレットがなければNone
+        #[ink(message)]
+        pub fn palette_of(&self, id: TokenId) -> Option<u32> {
+            self.token_palettes.get(id)
+        }
+
+        // レア度の表のキー1つ分を設定する。mintが始まる前だけ変更できる
+        // entriesが空ならそのキーを表から外す
+        #[ink(message)]
+        pub fn set_rarity_table(
+            &mut self,
+            key: String,
+            entries: Vec<(String, u32)>,
+        ) -> Result<(), Error> {
+            self.ensure_owner()?;
+            if self.minted_count > 0 {
+                return Err(Error::MintAlreadyStarted);
+            }
+            if key.is_empty()
+                || key.len() > MAX_ATTRIBUTE_KEY_LEN
+                || entries
+                    .iter()
+                    .any(|(value, _)| value.len() > MAX_ATTRIBUTE_VALUE_LEN)
+            {
+                return Err(Error::AttributeTooLong);
+            }
+            if entries.len() > MAX_RARITY_VALUES
+                || (!entries.is_empty() && entries.iter().all(|(_, weight)| *weight == 0))
+            {
+                return Err(Error::InvalidParameter);
+            }
+
+            let mut table = self.rarity_table();
+            let pos = table.iter().position(|(k, _)| *k == key);
+            match (pos, entries.is_empty()) {
+                (Some(pos), true) => {
+                    table.remove(pos);
+                }
+                (Some(pos), false) => table[pos].1 = entries,
+                (None, true) => return Ok(()),
+                (None, false) => {
+                    if table.len() >= MAX_ATTRIBUTES {
+                        return Err(Error::TooManyAttributes);
+                    }
+                    table.push((key, entries));
+                }
+            }
+            self.rarity_table.set(&table);
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn rarity_table(&self) -> RarityTable {
+            self.rarity_table.get().unwrap_or_default()
+        }
+
+        // キーと値の長さ・数を確かめて属性を書き込む
+        fn write_attribute(
+            &mut self,
+            id: TokenId,
+            key: String,
+            value: String,
+        ) -> Result<(), Error> {
+            if key.is_empty()
+                || key.len() > MAX_ATTRIBUTE_KEY_LEN
+                || value.len() > MAX_ATTRIBUTE_VALUE_LEN
+            {
+                return Err(Error::AttributeTooLong);
+            }
+
+            let mut keys = self.attribute_keys_of(id);
+            if !keys.contains(&key) {
+                if keys.len() >= MAX_ATTRIBUTES {
+                    return Err(Error::TooManyAttributes);
+                }
+                keys.push(key.clone());
+                self.attribute_keys.insert(id, &keys);
+            }
+            // 値が変わったら属性ごとの一覧を付け替える
+            let old = self.attributes.get((id, key.clone()));
+            if old.as_ref() != Some(&value) {
+                if let Some(old) = old {
+                    self.remove_from_attribute_tokens(id, &key, &old);
+                }
+                self.add_to_attribute_tokens(id, &key, &value);
+                self.attributes.insert((id, key.clone()), &value);
+            }
+
+            self.env().emit_event(AttributeSet {
+                id,
+                key,
+                value: Some(value),
+            });
+            Ok(())
+        }
+
+        // トークンの属性を削除する
+        #[ink(message)]
+        pub fn remove_attribute(&mut self, id: TokenId, key: String) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.owner_or_err(id)?;
+            self.ensure_not_frozen(id)?;
+
+            let mut keys = self.attribute_keys_of(id);
+            let Some(pos) = keys.iter().position(|k| *k == key) else {
+                return Ok(());
+            };
+            keys.swap_remove(pos);
+            if keys.is_empty() {
+                self.attribute_keys.remove(id);
+            } else {
+                self.attribute_keys.insert(id, &keys);
+            }
+            if let Some(value) = self.attributes.take((id, key.clone())) {
+                self.remove_from_attribute_tokens(id, &key, &value);
+            }
+
+            self.env().emit_event(AttributeSet {
+                id,
+                key,
+                value: None,
+            });
+            self.env().emit_event(MetadataUpdate { id });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn get_attribute(&self, id: TokenId, key: String) -> Option<String> {
+            self.attributes.get((id, key))
+        }
+
+        // 属性keyがvalueのトークンの数
+        #[ink(message)]
+        pub fn attribute_token_count(&self, key: String, value: String) -> u32 {
+            self.attribute_tokens_count.get((key, value)).unwrap_or(0)
+        }
+
+        // 属性keyがvalueのトークンをoffset番目からlimit個返す。limitがmax_batch_sizeを超えたらエラー
+        // 属性の変更やburnで順番は入れ替わる
+        #[ink(message)]
+        pub fn tokens_with_attribute(
+            &self,
+            key: String,
+            value: String,
+            offset: u32,
+            limit: u32,
+        ) -> Result<Vec<TokenId>, Error> {
+            self.ensure_batch_size(limit)?;
+            let count = self.attribute_tokens_count.get((&key, &value)).unwrap_or(0);
+            let start = offset.min(count);
+            let end = start.saturating_add(limit).min(count);
+            Ok((start..end)
+                .filter_map(|index| self.attribute_tokens.get((&key, &value, index)))
+                .collect())
+        }
+
+        // トークンに設定されている属性のキー一覧
+        #[ink(message)]
+        pub fn attribute_keys_of(&self, id: TokenId) -> Vec<String> {
+            self.attribute_keys.get(id).unwrap_or_default()
+        }
+
+        // トークンを描画するHTML。スケッチの前にtokenId・seed・attributesを定義しておく
+        #[ink(message)]
+        pub fn token_html(&self, id: TokenId) -> Result<String, Error> {
+            self.owner_or_err(id)?;
+            let sketch = self.script();
+            let mut buf = [0u8; MAX_ID_DIGITS];
+            let digits = format_id(id, &mut buf);
+
+            let mut html = String::with_capacity(512 + sketch.len());
+            html.push_str("<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>");
+            push_html_text(&mut html, &self.name);
+            html.push_str(" #");
+            html.push_str(digits);
+            html.push_str("</title><script src=\"");
+            html.push_str(P5_LIBRARY_URL);
+            html.push_str("\"></script><script>const tokenId=\"");
+            html.push_str(digits);
+            html.push_str("\";const seed=\"0x");
+            push_hex(&mut html, &self.seeds.get(id).unwrap_or_default());
+            html.push_str("\";const attributes={");
+            for (i, key) in self.attribute_keys_of(id).into_iter().enumerate() {
+                let value = self.attributes.get((id, key.clone())).unwrap_or_default();
+                if i > 0 {
+                    html.push(',');
+                }
+                push_json_str(&mut html, &key);
+                html.push(':');
+                push_json_str(&mut html, &value);
+            }
+            html.push_str("};const palette=[");
+            let colors = self
+                .palette_of(id)
+                .and_then(|palette| self.palettes.get(palette))
+                .map(|(_, colors)| colors)
+                .unwrap_or_default();
+            for (i, color) in colors.iter().enumerate() {
+                if i > 0 {
+                    html.push(',');
+                }
+                html.push_str("\"#");
+                push_hex(&mut html, color);
+                html.push('"');
+            }
+            html.push_str("];</script><script>");
+            html.push_str(&sketch);
+            html.push_str("</script></head><body></body></html>");
+            Ok(html)
+        }
+
+        // スクリプトのチャンクをアップロードする。既存のチャンクの上書きか末尾への追加だけできる
+        // スクリプトもメタデータの一部なのでfreeze_all_metadataのあとは変えられない
+        #[ink(message)]
+        pub fn upload_script_chunk(&mut self, index: u32, bytes: Vec<u8>) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.ensure_metadata_mutable()?;
+            if bytes.len() > MAX_SCRIPT_CHUNK_LEN {
+                return Err(Error::ScriptChunkTooLarge);
+            }
+            if index > self.script_size {
+                return Err(Error::InvalidParameter);
+            }
+
+            self.script_chunks.insert(index, &bytes);
+            if index == self.script_size {
+                self.script_size += 1;
+            }
+            Ok(())
+        }
+
+        // アップロードしたスクリプトを全部消す
+        #[ink(message)]
+        pub fn clear_script(&mut self) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.ensure_metadata_mutable()?;
+            for index in 0..self.script_size {
+                self.script_chunks.remove(index);
+            }
+            self.script_size = 0;
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn script_chunk(&self, index: u32) -> Option<Vec<u8>> {
+            self.script_chunks.get(index)
+        }
+
+        // スクリプトのチャンク数。クライアントは0から順に取得して連結する
+        #[ink(message)]
+        pub fn script_size(&self) -> u32 {
+            self.script_size
+        }
+
+        // p5.jsのスケッチのsha256を約束する。メタデータを全部固定したあとは変えられない
+        #[ink(message)]
+        pub fn set_script_hash(&mut self, hash: [u8; 32]) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.ensure_metadata_mutable()?;
+            self.script_hash.set(&hash);
+            self.env().emit_event(ScriptHashSet { hash });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn script_hash(&self) -> Option<[u8; 32]> {
+            self.script_hash.get()
+        }
+
+        // bytesのsha256が約束したハッシュと一致するか。ハッシュが未設定ならfalse
+        #[ink(message)]
+        pub fn verify_script(&self, bytes: Vec<u8>) -> bool {
+            let Some(expected) = self.script_hash() else {
+                return false;
+            };
+            let mut hash = [0u8; 32];
+            ink::env::hash_bytes::<ink::env::hash::Sha2x256>(&bytes, &mut hash);
+            hash == expected
+        }
+
+        // チャンクを連結したスクリプト(チャンクの境目で文字が切れていても連結後に復元される)
+        fn script(&self) -> String {
+            let mut bytes = Vec::new();
+            for index in 0..self.script_size {
+                bytes.extend(self.script_chunks.get(index).unwrap_or_default());
+            }
+            String::from_utf8_lossy(&bytes).into_owned()
+        }
+
+        // p5.jsを実行できない環境向けの画像。シードだけから決まる
+        #[ink(message)]
+        pub fn token_svg(&self, id: TokenId) -> Result<String, Error> {
+            self.owner_or_err(id)?;
+            Ok(seed_svg(&self.seeds.get(id).unwrap_or_default()))
+        }
+
+        // チェーン上の情報だけでERC-721のメタデータJSONを組み立てる
+        // 属性のうちdescription・image・animation_urlはトップレベルに、それ以外はattributesに入れる
+        #[ink(message)]
+        pub fn token_metadata_json(&self, id: TokenId) -> Result<String, Error> {
+            self.owner_or_err(id)?;
+            let mut buf = [0u8; MAX_ID_DIGITS];
+            let digits = format_id(id, &mut buf);
+
+            // 所有者が名前を付けていればそれを使う
+            let mut json = String::from("{\"name\":");
+            let name = self.token_names.get(id).unwrap_or_else(|| {
+                let mut name = String::with_capacity(self.name.len() + 2 + digits.len());
+                name.push_str(&self.name);
+                name.push_str(" #");
+                name.push_str(digits);
+                name
+            });
+            push_json_str(&mut json, &name);
+
+            // 所有者の説明は属性のdescriptionより優先する
+            let description = self.token_descriptions.get(id);
+            if let Some(description) = &description {
+                json.push_str(",\"description\":");
+                push_json_str(&mut json, description);
+            }
+
+            // 個別・既定のanimation_urlは属性のanimation_urlより優先する
+            let animation_url = self.animation_url(id);
+
+            let mut traits = String::new();
+            let mut has_image = false;
+            for key in self.attribute_keys_of(id) {
+                let Some(value) = self.attributes.get((id, key.clone())) else {
+                    continue;
+                };
+                match key.as_str() {
+                    "description" if description.is_some() => {}
+                    "animation_url" if animation_url.is_some() => {}
+                    "description" | "image" | "animation_url" => {
+                        has_image |= key == "image";
+                        json.push(',');
+                        push_json_str(&mut json, &key);
+                        json.push(':');
+                        push_json_str(&mut json, &value);
+                    }
+                    _ => {
+                        if !traits.is_empty() {
+                            traits.push(',');
+                        }
+                        traits.push_str("{\"trait_type\":");
+                        push_json_str(&mut traits, &key);
+                        traits.push_str(",\"value\":");
+                        push_json_str(&mut traits, &value);
+                        traits.push('}');
+                    }
+                }
+            }
+
+            if let Some(url) = self.external_urls.get(id) {
+                json.push_str(",\"external_url\":");
+                push_json_str(&mut json, &url);
+            }
+            if let Some(url) = &animation_url {
+                json.push_str(",\"animation_url\":");
+                push_json_str(&mut json, url);
+            }
+
+            // imageがなければシードから作ったSVGを使う
+            if !has_image {
+                json.push_str(",\"image\":");
+                let svg = self.token_svg(id)?.replace('#', "%23");
+                let mut uri = String::from("data:image/svg+xml;utf8,");
+                uri.push_str(&svg);
+                push_json_str(&mut json, &uri);
+            }
+
+            json.push_str(",\"attributes\":[");
+            json.push_str(&traits);
+            json.push_str("]}");
+            Ok(json)
+        }
+
+        // 間違ってこのコントラクトに送られたPSP22トークンを回収する
+        #[ink(message)]
+        pub fn sweep_psp22(
+            &mut self,
+            token: AccountId,
+            to: AccountId,
+            amount: Balance,
+        ) -> Result<(), Error> {
+            self.ensure_owner()?;
+            internal::ensure_not_zero(&to)?;
+            self.call_foreign_transfer::<_, PSP22Error>(token, PSP22_TRANSFER_SELECTOR, to, amount)
+        }
+
+        // 間違ってこのコントラクトに送られたPSP34トークンを回収する
+        #[ink(message)]
+        pub fn sweep_psp34(
+            &mut self,
+            collection: AccountId,
+            id: Id,
+            to: AccountId,
+        ) -> Result<(), Error> {
+            self.ensure_owner()?;
+            internal::ensure_not_zero(&to)?;
+            self.call_foreign_transfer::<_, PSP34Error>(collection, PSP34_TRANSFER_SELECTOR, to, id)
+        }
+
+        // ベースURIを変更する
+        #[ink(message)]
+        pub fn set_base_uri(&mut self, new_uri: String) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.ensure_metadata_mutable()?;
+            if new_uri.is_empty() {
+                return Err(Error::InvalidParameter);
+            }
+            self.base_uri = new_uri.clone();
+
+            // イベント発火
+            self.env().emit_event(BaseUriChanged { base_uri: new_uri });
+            self.emit_batch_metadata_update();
+
+            Ok(())
+        }
+
+        // リビール前のURIを設定する。設定するとリビールするまで全トークンがこのURIになる
+        #[ink(message)]
+        pub fn set_placeholder_uri(&mut self, uri: String) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.ensure_metadata_mutable()?;
+            if self.revealed {
+                return Err(Error::AlreadyRevealed);
+            }
+            self.placeholder_uri.set(&uri);
+            self.emit_batch_metadata_update();
+            Ok(())
+        }
+
+        // リビールして本来のURIを返すようにする
+        // base_uriを渡すとベースURIも差し替える。random_offsetならメタデータの番号をランダムにずらす
+        #[ink(message)]
+        pub fn reveal(
+            &mut self,
+            base_uri: Option<String>,
+            random_offset: bool,
+        ) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.ensure_metadata_mutable()?;
+            if self.revealed {
+                return Err(Error::AlreadyRevealed);
+            }
+            if let Some(base_uri) = base_uri {
+                if base_uri.is_empty() {
+                    return Err(Error::InvalidParameter);
+                }
+                self.base_uri = base_uri;
+            }
+            if random_offset {
+                // TokenId::MAXはmintされないのでトークンのシードとは被らない
+                let seed = self.derive_seed(TokenId::MAX, &[])?;
+                let mut bytes = [0u8; 16];
+                bytes.copy_from_slice(&seed[..16]);
+                self.reveal_offset = TokenId::from_le_bytes(bytes) % self.supply_cap();
+            }
+            self.revealed = true;
+
+            self.env().emit_event(Revealed {
+                base_uri: self.base_uri.clone(),
+                offset: self.reveal_offset,
+            });
+            self.emit_batch_metadata_update();
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn is_revealed(&self) -> bool {
+            self.revealed
+        }
+
+        // メタデータのIPFSのCID。未設定なら空文字
+        #[ink(message)]
+        pub fn base_cid(&self) -> String {
+            self.base_cid.get().unwrap_or_default()
+        }
+
+        // ベースCIDを変更する。空文字ならベースURIに戻す
+        #[ink(message)]
+        pub fn set_base_cid(&mut self, cid: String) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.ensure_metadata_mutable()?;
+            if !cid.is_empty()
+                && (cid.len() < MIN_CID_LEN
+                    || cid.len() > MAX_CID_LEN
+                    || !cid.bytes().all(|b| b.is_ascii_alphanumeric()))
+            {
+                return Err(Error::InvalidCid);
+            }
+            self.base_cid.set(&cid);
+
+            // イベント発火
+            self.env().emit_event(BaseCidChanged { base_cid: cid });
+            self.emit_batch_metadata_update();
+
+            Ok(())
+        }
+
+        // token_uriのテンプレート。未設定なら空文字
+        #[ink(message)]
+        pub fn uri_template(&self) -> String {
+            self.uri_template.get().unwrap_or_default()
+        }
+
+        // token_uriのテンプレートを変更する。例: https://host/{id}/meta?seed={seed}
+        // 空文字ならCIDやベースURIに戻す
+        #[ink(message)]
+        pub fn set_uri_template(&mut self, template: String) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.ensure_metadata_mutable()?;
+            if template.len() > MAX_URL_LEN {
+                return Err(Error::TextTooLong);
+            }
+            self.uri_template.set(&template);
+            self.emit_batch_metadata_update();
+            Ok(())
+        }
+
+        // コレクションのmetadata.jsonのURI。未設定なら空文字
+        #[ink(message)]
+        pub fn contract_uri(&self) -> String {
+            self.contract_uri.get().unwrap_or_default()
+        }
+
+        // コレクションのURIを変更する
+        #[ink(message)]
+        pub fn set_contract_uri(&mut self, new_uri: String) -> Result<(), Error> {
+            self.ensure_owner()?;
+            self.contract_uri.set(&new_uri);
+
+            // イベント発火
+            self.env().emit_event(ContractUriChanged {
+                contract_uri: new_uri,
+            });
+
+            Ok(())
+        }
+
+        // 1回のメッセージで扱える件数の上限を変更する
+        #[ink(message)]
+        pub fn set_max_batch_size(&mut self, max_batch_size: u32) -> Result<(), Error> {
+            self.ensure_owner()?;
+            if max_batch_size == 0 {
+                return Err(Error::InvalidParameter);
+            }
+            self.max_batch_size = max_batch_size;
+            Ok(())
+        }
+
+        // ストレージの整合性をstartからlimit件チェックする(デバッグビルドのみ)
+        // アップグレード後のdry runやテストで使う
+        #[cfg(debug_assertions)]
+        #[ink(message)]
+        pub fn check_invariants(&self, start: TokenId, limit: u32) -> Result<(), Error> {
+            self.ensure_batch_size(limit)?;
+            let end = start.saturating_add(TokenId::from(limit));
+            for id in start..end {
+                match self.erc721.token_owner.get(id) {
+                    // 所有者がいるならその所有数は1以上
+                    Some(owner) => {
+                        if self.balance_of_or_zero(&owner) == 0 {
+                            return Err(Error::InvariantViolation);
+                        }
+                    }
+                    // 存在しないトークンにApprovalが残っていない
+                    None => {
+                        if self.erc721.token_approvals.contains(id) {
+                            return Err(Error::InvariantViolation);
+                        }
+                    }
+                }
+                // ビットマップと所有者が一致している
+                if self.live_bit(id) != self.exists(id) {
+                    return Err(Error::InvariantViolation);
+                }
+                // まだmintしていないidに所有者はいない
+                if id >= self.token_id && self.exists(id) {
+                    return Err(Error::InvariantViolation);
+                }
+            }
+            Ok(())
+        }
+
+        // 指定のインターフェースを実装しているか
+        #[ink(message)]
+        pub fn supports_interface(&self, interface_id: [u8; 4]) -> bool {
+            let supported = [
+                INTERFACE_ID_ERC165,
+                INTERFACE_ID_ERC721,
+                INTERFACE_ID_ERC721_METADATA,
+                INTERFACE_ID_ERC4906,
+            ];
+            supported.contains(&interface_id)
+                || (cfg!(feature = "enumerable") && interface_id == INTERFACE_ID_ERC721_ENUMERABLE)
+        }
+
+        // バージョンや有効な機能などコントラクトの情報
+        #[ink(message)]
+        pub fn contract_info(&self) -> ContractInfo {
+            ContractInfo {
+                version: String::from(env!("CARGO_PKG_VERSION")),
+                features: FEATURES.iter().map(|f| String::from(*f)).collect(),
+                storage_version: STORAGE_VERSION,
+                code_hash: self.env().own_code_hash().ok(),
+            }
+        }
+
+        // mintしたときに増えるストレージの見積もり
+        #[ink(message)]
+        pub fn estimate_mint_deposit(&self, to: AccountId) -> Result<StorageEstimate, Error> {
+            self.estimate_mint_many_deposit(to, 1)
+        }
+
+        // n個mintしたときに増えるストレージの見積もり
+        #[ink(message)]
+        pub fn estimate_mint_many_deposit(
+            &self,
+            to: AccountId,
+            n: u32,
+        ) -> Result<StorageEstimate, Error> {
+            self.ensure_batch_size(n)?;
+            if n == 0 {
+                return Ok(StorageEstimate::default());
+            }
+
+            // トークンごとに所有者・mint時刻・mint者・シード・シードの材料のエントリが増える
+            // シードの材料はエントロピーが最大の長さとして数えるので、見積もりは上限になる
+            let now: Timestamp = 0;
+            let source: SeedSource = (to, 0, ink::prelude::vec![0; MAX_ENTROPY_LEN]);
+            let mut items = 5u32;
+            let mut bytes = (Encode::encoded_size(&to) * 2
+                + Encode::encoded_size(&(now, now))
+                + Encode::encoded_size(&[0u8; 32])
+                + Encode::encoded_size(&source)) as u32;
+            if self.palette_count() > 0 {
+                items += 1;
+                bytes += Encode::encoded_size(&0u32) as u32;
+            }
+            // 全トークンと所有者ごとの一覧とその位置
+            if cfg!(feature = "enumerable") {
+                items += 4;
+                bytes += (Encode::encoded_size(&TokenId::default()) * 2
+                    + Encode::encoded_size(&0u64) * 2) as u32;
+            }
+
+            // レア度の表の属性。値は一番長いものとして数える
+            let table = self.rarity_table();
+            if !table.is_empty() {
+                let keys: Vec<&String> = table.iter().map(|(key, _)| key).collect();
+                items += 1;
+                bytes += Encode::encoded_size(&keys) as u32;
+            }
+            let mut value_counts = StorageEstimate::default();
+            for (key, entries) in &table {
+                let value_bytes = entries
+                    .iter()
+                    .map(|(value, _)| Encode::encoded_size(value))
+                    .max()
+                    .unwrap_or(0);
+                // 値、属性ごとの一覧とその位置
+                items += 3;
+                bytes += (value_bytes
+                    + Encode::encoded_size(&TokenId::default())
+                    + Encode::encoded_size(&0u32)) as u32;
+                // まだ誰も持っていない値は数のエントリが増える(n個までしか増えない)
+                let missing = entries
+                    .iter()
+                    .filter(|(value, _)| {
+                        !self
+                            .attribute_tokens_count
+                            .contains((key.as_str(), value.as_str()))
+                    })
+                    .count() as u32;
+                let added = missing.min(n);
+                value_counts.items += added;
+                value_counts.bytes += added * Encode::encoded_size(&0u32) as u32;
+            }
+
+            let mut estimate = StorageEstimate {
+                items: items.saturating_mul(n).saturating_add(value_counts.items),
+                bytes: bytes.saturating_mul(n).saturating_add(value_counts.bytes),
+            };
+
+            // 生存ビットマップは256個ごとに1エントリ
+            let word_bytes = Encode::encoded_size(&[0u64; 4]) as u32;
+            let mut last_word = None;
+            for i in 0..n {
+                let id = self
+                    .token_id
+                    .saturating_add(TokenId::from(i).saturating_mul(self.id_step));
+                let word = id / 256;
+                if last_word != Some(word) && !self.live_bitmap.contains(word) {
+                    estimate.items += 1;
+                    estimate.bytes += word_bytes;
+                }
+                last_word = Some(word);
+            }
+
+            // 初めてトークンを持つアカウントは所有数のエントリが増える
+            if !self.erc721.owned_tokens_count.contains(to) {
+                let count = self.balance_of_or_zero(&to);
+                estimate.items += 1;
+                estimate.bytes += Encode::encoded_size(&count) as u32;
+            }
+
+            Ok(estimate)
+        }
+
+        fn transfer_token_from(
+            &mut self,
+            from: &AccountId,
+            to: &AccountId,
+            id: TokenId,
+            memo: Option<String>,
+        ) -> Result<(), Error> {
+            let caller = self.env().caller();
+
+            if memo.as_ref().is_some_and(|m| m.len() > MAX_MEMO_LEN) {
+                return Err(Error::MemoTooLong);
+            }
+
+            self.check_transfer(caller, from, to, id)?;
+            self.before_token_transfer(Some(from), Some(to), id)?;
+
+            // Approval情報をクリア
+            self.clear_approval(id);
+            // トークンの所有数を更新
+            self.decrease_balance(from)?;
+            self.increase_balance(to)?;
+            // 所有者は上書きするので削除はいらない
+            self.erc721.token_owner.insert(id, to);
+            self.after_token_transfer(Some(from), Some(to), id);
+            // 転送回数と時刻を記録
+            let count = self.transfer_count(id).saturating_add(1);
+            self.transfer_counts.insert(id, &count);
+            let now = self.env().block_timestamp();
+            let minted_at = self.minted_at(id).unwrap_or(now);
+            self.token_timestamps.insert(id, &(minted_at, now));
+
+            // イベント発火
+            self.env().emit_event(Transfer {
+                from: Some(*from),
+                to: Some(*to),
+                id,
+                memo,
+            });
+
+            Ok(())
+        }
+
+        // 転送できるかだけを確認する
+        fn check_transfer(
+            &self,
+            caller: AccountId,
+            from: &AccountId,
+            to: &AccountId,
+            id: TokenId,
+        ) -> Result<(), Error> {
+            // 所有者は一回だけ読んで使い回す
+            let owner = self.owner_or_err(id)?;
+            if owner != *from {
+                return Err(Error::NotOwner);
+            }
+
+            self.approved_or_owner(caller, owner, id)?;
+            internal::ensure_not_zero(to)?;
+            if to == from {
+                return Err(Error::TransferToSelf);
+            }
+            Ok(())
+        }
+
+        // count個mintできるかだけを確認する(代金は見ない)
+        fn check_mint(&self, to: &AccountId, count: u32) -> Result<(), Error> {
+            if count == 0 {
+                return Err(Error::InvalidParameter);
+            }
+            self.ensure_batch_size(count)?;
+
+            // 上限まで発行済み
+            let minted = self.minted_count.saturating_add(u64::from(count));
+            if u128::from(minted) > self.supply_cap() {
+                return Err(Error::MaxSupplyReached);
+            }
+
+            internal::ensure_not_zero(to)
+        }
+
+        // burnしたトークンに紐づくストレージを削除する
+        // トークンごとのMappingを追加したらここにも追加すること
+        fn clear_token_data(&mut self, id: TokenId) {
+            self.clear_approval(id);
+            self.token_notes.remove(id);
+            self.token_names.remove(id);
+            self.token_descriptions.remove(id);
+            self.transfer_counts.remove(id);
+            self.token_timestamps.remove(id);
+            self.minters.remove(id);
+            self.uri_overrides.remove(id);
+            self.token_uris.remove(id);
+            self.external_urls.remove(id);
+            self.animation_urls.remove(id);
+            self.artist_signatures.remove(id);
+            self.rerolled.remove(id);
+            self.token_palettes.remove(id);
+            self.seeds.remove(id);
+            self.seed_sources.remove(id);
+            self.token_editions.remove(id);
+            self.frozen_metadata.remove(id);
+            for key in self.attribute_keys.take(id).unwrap_or_default() {
+                if let Some(value) = self.attributes.take((id, key.clone())) {
+                    self.remove_from_attribute_tokens(id, &key, &value);
+                }
+            }
+        }
+
+        // 所有者から外したトークンの後始末をしてイベントを出す
+        fn finish_burn(&mut self, owner: &AccountId, caller: AccountId, id: TokenId) {
+            self.after_token_transfer(Some(owner), None, id);
+            // トークンに紐づく情報を全部削除してデポジットを返してもらう
+            self.clear_token_data(id);
+            self.burned_count += 1;
+            #[cfg(feature = "enumerable")]
+            self.remove_from_all_tokens(id);
+
+            // イベント発火
+            self.env().emit_event(Transfer {
+                from: Some(*owner),
+                to: None,
+                id,
+                memo: None,
+            });
+            self.env().emit_event(Burned {
+                id,
+                owner: *owner,
+                operator: caller,
+            });
+        }
+
+        // n個分の代金を確認してからmintする
+        fn paid_mint(
+            &mut self,
+            to: &AccountId,
+            n: u32,
+            entropy: &[u8],
+        ) -> Result<Vec<TokenId>, Error> {
+            self.check_mint(to, n)?;
+
+            // 代金が足りない・多すぎる(多い分がコントラクトに残らないようにする)
+            let price = self.mint_price.saturating_mul(Balance::from(n));
+            let paid = self.env().transferred_value();
+            if paid < price {
+                return Err(Error::InsufficientPayment);
+            }
+            if paid > price {
+                return Err(Error::UnexpectedValue);
+            }
+
+            // gasが足りない分はmintせず、その分の代金を返す
+            let count = self.affordable_mints(n);
+            let ids = self.mint_batch(to, count, entropy)?;
+            let refund = self.mint_price.saturating_mul(Balance::from(n - count));
+            if refund > 0 {
+                self.env()
+                    .transfer(self.env().caller(), refund)
+                    .map_err(|_| Error::TransferFailed)?;
+            }
+            self.emit_batch_mint_truncated(to, n, count);
+            self.record_revenue(paid - refund);
+            Ok(ids)
+        }
+
+        // 残りのgasでmintできる数。trapしないよう途中で止め、少なくとも1個はmintする
+        fn affordable_mints(&self, n: u32) -> u32 {
+            let left = self.gas_left().saturating_sub(MINT_GAS_RESERVE);
+            let affordable = u32::try_from(left / MINT_GAS_PER_TOKEN).unwrap_or(u32::MAX);
+            n.min(affordable.max(1))
+        }
+
+        #[cfg(not(test))]
+        fn gas_left(&self) -> u64 {
+            self.env().gas_left()
+        }
+
+        // オフチェーン環境ではgas_leftが使えないのでテストから設定する
+        #[cfg(test)]
+        fn gas_left(&self) -> u64 {
+            tests::GAS_LEFT.with(|gas| gas.get())
+        }
+
+        // requested個のうちminted個で止めたときに続きを知らせる
+        fn emit_batch_mint_truncated(&self, to: &AccountId, requested: u32, minted: u32) {
+            if minted >= requested {
+                return;
+            }
+            self.env().emit_event(BatchMintTruncated {
+                to: *to,
+                minted,
+                remaining: requested - minted,
+                next_id: self.token_id,
+            });
+        }
+
+        // toへ連番でn個mintする。所有数の更新は一回だけで、イベントはトークンごとに出す
+        fn mint_batch(
+            &mut self,
+            to: &AccountId,
+            n: u32,
+            entropy: &[u8],
+        ) -> Result<Vec<TokenId>, Error> {
+            self.check_mint(to, n)?;
+
+            // 桁あふれすると同じIDができてしまうので、次のIDまで計算できなければ失敗にする
+            let mut ids = Vec::with_capacity(n as usize);
+            let mut id = self.token_id;
+            for i in 0..n {
+                if i > 0 {
+                    id = id.checked_add(self.id_step).ok_or(Error::Overflow)?;
+                }
+                ids.push(id);
+            }
+            let next = id.checked_add(self.id_step).ok_or(Error::Overflow)?;
+            // 乱数が取れないときは何も変更しないうちに失敗させる
+            let seeds = ids
+                .iter()
+                .map(|&id| self.derive_seed(id, entropy))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for &id in &ids {
+                self.before_token_transfer(None, Some(to), id)?;
+            }
+            self.add_tokens_to(to, &ids)?;
+            let now = self.env().block_timestamp();
+            let table = self.rarity_table();
+            let palette_count = self.palette_count();
+            let source = (
+                self.env().caller(),
+                self.env().block_number(),
+                entropy.to_vec(),
+            );
+            for (&id, seed) in ids.iter().zip(&seeds) {
+                self.after_token_transfer(None, Some(to), id);
+                self.token_timestamps.insert(id, &(now, now));
+                self.minters.insert(id, to);
+                self.seeds.insert(id, seed);
+                self.seed_sources.insert(id, &source);
+                self.assign_traits(id, seed, &table)?;
+                if let Some(palette) = pick_palette(seed, palette_count) {
+                    self.token_palettes.insert(id, &palette);
+                }
+                self.minted_count += 1;
+                #[cfg(feature = "enumerable")]
+                self.add_to_all_tokens(id);
+
+                // イベント発火
+                self.env().emit_event(Transfer {
+                    from: None,
+                    to: Some(*to),
+                    id,
+                    memo: None,
+                });
+            }
+
+            // 次のTokenIdへ進める
+            self.token_id = next;
+
+            Ok(ids)
+        }
+
+        // レア度の表に従ってシードから属性を選んで書き込む
+        fn assign_traits(
+            &mut self,
+            id: TokenId,
+            seed: &[u8; 32],
+            table: &RarityTable,
+        ) -> Result<(), Error> {
+            for (salt, (key, entries)) in (0u32..).zip(table) {
+                if let Some(i) = pick_weighted(seed, salt, entries) {
+                    self.write_attribute(id, key.clone(), entries[i].0.clone())?;
+                }
+            }
+            Ok(())
+        }
+
+        // URIに使うメタデータの番号。mint順をreveal_offsetだけずらして上限で折り返す
+        fn metadata_id(&self, id: TokenId) -> TokenId {
+            if self.reveal_offset == 0 {
+                return id;
+            }
+            // (index + offset) % capを桁あふれしないように計算する
+            let cap = self.supply_cap();
+            let index = (id - self.first_id) / self.id_step % cap;
+            let rest = cap - self.reveal_offset;
+            let index = if index >= rest {
+                index - rest
+            } else {
+                index + self.reveal_offset
+            };
+            index
+                .checked_mul(self.id_step)
+                .and_then(|n| n.checked_add(self.first_id))
+                .unwrap_or(id)
+        }
+
+        // mintするトークンのシードを(呼び出し元, ブロック番号, TokenId, エントロピー)のハッシュから作る
+        // randomnessフィーチャーではそのハッシュをsubjectにしてノードの乱数を使う
+        fn derive_seed(&self, id: TokenId, entropy: &[u8]) -> Result<[u8; 32], Error> {
+            let mut subject = [0u8; 32];
+            ink::env::hash_encoded::<ink::env::hash::Blake2x256, _>(
+                &(self.env().caller(), self.env().block_number(), id, entropy),
+                &mut subject,
+            );
+            self.random_seed(subject)
+        }
+
+        // randomnessフィーチャーではsubjectからノードの乱数を取り、なければsubjectをそのまま使う
+        fn random_seed(&self, subject: [u8; 32]) -> Result<[u8; 32], Error> {
+            #[cfg(feature = "randomness")]
+            let subject = self
+                .env()
+                .extension()
+                .fetch_random(subject)
+                .map_err(|_| Error::RandomnessUnavailable)?;
+            Ok(subject)
+        }
+
+        // 属性ごとのトークン一覧の最後に追加する
+        fn add_to_attribute_tokens(&mut self, id: TokenId, key: &str, value: &str) {
+            let index = self.attribute_tokens_count.get((key, value)).unwrap_or(0);
+            self.attribute_tokens.insert((key, value, index), &id);
+            self.attribute_tokens_index.insert((id, key), &index);
+            self.attribute_tokens_count
+                .insert((key, value), &(index + 1));
+        }
+
+        // 属性ごとのトークン一覧から外し、最後の要素を空いた位置へ移す
+        fn remove_from_attribute_tokens(&mut self, id: TokenId, key: &str, value: &str) {
+            let Some(index) = self.attribute_tokens_index.take((id, key)) else {
+                return;
+            };
+            let last = self
+                .attribute_tokens_count
+                .get((key, value))
+                .unwrap_or(1)
+                .saturating_sub(1);
+            if index != last {
+                if let Some(last_id) = self.attribute_tokens.get((key, value, last)) {
+                    self.attribute_tokens.insert((key, value, index), &last_id);
+                    self.attribute_tokens_index.insert((last_id, key), &index);
+                }
+            }
+            self.attribute_tokens.remove((key, value, last));
+            if last == 0 {
+                self.attribute_tokens_count.remove((key, value));
+            } else {
+                self.attribute_tokens_count.insert((key, value), &last);
+            }
+        }
+
+        // 全トークン一覧の最後に追加する。minted_countを進めたあとに呼ぶ
+        #[cfg(feature = "enumerable")]
+        fn add_to_all_tokens(&mut self, id: TokenId) {
+            let index = self.total_supply() - 1;
+            self.all_tokens.insert(index, &id);
+            self.all_tokens_index.insert(id, &index);
+        }
+
+        // 全トークン一覧から外し、最後の要素を空いた位置へ移す。burned_countを進めたあとに呼ぶ
+        #[cfg(feature = "enumerable")]
+        fn remove_from_all_tokens(&mut self, id: TokenId) {
+            let last = self.total_supply();
+            let Some(index) = self.all_tokens_index.take(id) else {
+                return;
+            };
+            if index != last {
+                if let Some(last_id) = self.all_tokens.get(last) {
+                    self.all_tokens.insert(index, &last_id);
+                    self.all_tokens_index.insert(last_id, &index);
+                }
+            }
+            self.all_tokens.remove(last);
+        }
+
+        // 所有者のトークン一覧のindex番目に追加する
+        #[cfg(feature = "enumerable")]
+        fn add_to_owner_tokens(&mut self, owner: &AccountId, id: TokenId, index: u64) {
+            self.owned_tokens.insert((owner, index), &id);
+            self.owned_tokens_index.insert(id, &index);
+        }
+
+        // 所有者のトークン一覧から外し、最後(last番目)の要素を空いた位置へ移す
+        #[cfg(feature = "enumerable")]
+        fn remove_from_owner_tokens(&mut self, owner: &AccountId, id: TokenId, last: u64) {
+            let Some(index) = self.owned_tokens_index.take(id) else {
+                return;
+            };
+            if index != last {
+                if let Some(last_id) = self.owned_tokens.get((owner, last)) {
+                    self.owned_tokens.insert((owner, index), &last_id);
+                    self.owned_tokens_index.insert(last_id, &index);
+                }
+            }
+            self.owned_tokens.remove((owner, last));
+        }
+
+        fn live_bit(&self, id: TokenId) -> bool {
+            let (index, bit) = (id / 256, id % 256);
+            let word = self.live_bitmap.get(index).unwrap_or_default();
+            word[(bit / 64) as usize] & (1 << (bit % 64)) != 0
+        }
+
+        // 存在するトークンのビットを立てる・落とす
+        fn set_live_bit(&mut self, id: TokenId, live: bool) {
+            let (index, bit) = (id / 256, id % 256);
+            let mut word = self.live_bitmap.get(index).unwrap_or_default();
+            let mask = 1u64 << (bit % 64);
+            if live {
+                word[(bit / 64) as usize] |= mask;
+            } else {
+                word[(bit / 64) as usize] &= !mask;
+            }
+
+            if word == [0; 4] {
+                self.live_bitmap.remove(index);
+            } else {
+                self.live_bitmap.insert(index, &word);
+            }
+        }
+
+        fn approve_for(&mut self, to: &AccountId, id: TokenId) -> Result<(), Error> {
+            // 呼び出しもと
+            let caller = self.env().caller();
+            // トークン所有者
+            let owner = self.owner_or_err(id)?;
+
+            // 呼び出しもとと所有者が同じまたは、既にApproveされてる
+            if !(owner == caller || self.approved_for_all(owner, caller)) {
+                return Err(Error::NotApproved);
+            }
+
+            internal::ensure_not_zero(to)?;
+            if *to == owner {
+                return Err(Error::ApproveToOwner);
+            }
+
+            // 既にApproveがあれば上書きする
+            self.erc721.token_approvals.insert(id, to);
+
+            // イベント発火
+            self.env().emit_event(Approval {
+                from: caller,
+                to: *to,
+                id,
+            });
+
+            Ok(())
+        }
+
+        fn approve_for_all(&mut self, to: AccountId, approved: bool) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if to == caller {
+                return Err(Error::SelfApproval);
+            }
+
+            // 索引を更新
+            let already = self.approved_for_all(caller, to);
+            if approved != already {
+                let mut operators = self.operators_of(caller);
+                if approved {
+                    if operators.len() >= MAX_OPERATORS {
+                        return Err(Error::TooManyOperators);
+                    }
+                    operators.push(to);
+                } else {
+                    operators.retain(|o| *o != to);
+                }
+
+                if operators.is_empty() {
+                    self.operators.remove(caller);
+                } else {
+                    self.operators.insert(caller, &operators);
+                }
+            }
+
+            // イベント発火
+            self.env().emit_event(ApprovalForAll {
+                owner: caller,
+                operator: to,
+                approved,
+            });
+
+            if approved {
+                self.erc721.operator_approvals.insert((&caller, &to), &());
+            } else {
+                self.erc721.operator_approvals.remove((&caller, &to));
+            }
+
+            Ok(())
+        }
+
+        // mintできるトークンの上限。fixed-supplyのときはビルド時の値を使う
+        fn supply_cap(&self) -> TokenId {
+            #[cfg(feature = "fixed-supply")]
+            {
+                FIXED_MAX_SUPPLY
+            }
+            #[cfg(not(feature = "fixed-supply"))]
+            {
+                self.max_supply
+            }
+        }
+
+        fn set_uri_override(&mut self, id: TokenId, uri: Option<String>) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if !self.is_moderator(caller) {
+                return Err(Error::NotModerator);
+            }
+            if !self.exists(id) {
+                return Err(Error::TokenNotFound);
+            }
+
+            if let Some(uri) = &uri {
+                self.uri_overrides.insert(id, uri);
+            } else {
+                self.uri_overrides.remove(id);
+            }
+
+            // イベント発火
+            self.env().emit_event(TokenUriOverridden {
+                id,
+                moderator: caller,
+                uri,
+            });
+            self.env().emit_event(MetadataUpdate { id });
+
+            Ok(())
+        }
+
+        // オーナーかモデレーターだけがトークンのメタデータを変えられる
+        fn ensure_metadata_admin(&self, id: TokenId) -> Result<(), Error> {
+            let caller = self.env().caller();
+            if caller != self.owner && !self.is_moderator(caller) {
+                return Err(Error::NotOwner);
+            }
+            if !self.exists(id) {
+                return Err(Error::TokenNotFound);
+            }
+            self.ensure_not_frozen(id)
+        }
+
+        fn set_pinned_uri(&mut self, id: TokenId, uri: Option<String>) -> Result<(), Error> {
+            self.ensure_metadata_admin(id)?;
+
+            if let Some(uri) = &uri {
+                self.token_uris.insert(id, uri);
+            } else {
+                self.token_uris.remove(id);
+            }
+
+            // イベント発火
+            self.env().emit_event(TokenUriSet { id, uri });
+            self.env().emit_event(MetadataUpdate { id });
+
+            Ok(())
+        }
+
+        // 他のコントラクトのtransfer(to, value, data) -> Result<(), E>を呼び出す
+        fn call_foreign_transfer<V: Encode, E: Decode>(
+            &self,
+            contract: AccountId,
+            selector: [u8; 4],
+            to: AccountId,
+            value: V,
+        ) -> Result<(), Error> {
+            use ink::env::call::{build_call, ExecutionInput, Selector};
+
+            let result = build_call::<ink::env::DefaultEnvironment>()
+                .call(contract)
+                .exec_input(
+                    ExecutionInput::new(Selector::new(selector))
+                        .push_arg(to)
+                        .push_arg(value)
+                        .push_arg(Vec::<u8>::new()),
+                )
+                .returns::<Result<(), E>>()
+                .try_invoke();
+            foreign_transfer_result(result)
+        }
+
+        // 受け取り側のフックを呼び、決まった値が返ってこなければ失敗にする
+        fn call_on_received(
+            &self,
+            from: AccountId,
+            to: AccountId,
+            id: TokenId,
+            data: Vec<u8>,
+        ) -> Result<(), Error> {
+            use ink::env::call::{build_call, ExecutionInput, Selector};
+
+            let result = build_call::<ink::env::DefaultEnvironment>()
+                .call(to)
+                .exec_input(
+                    ExecutionInput::new(Selector::new(ON_ERC721_RECEIVED_SELECTOR))
+                        .push_arg(self.env().caller())
+                        .push_arg(from)
+                        .push_arg(id)
+                        .push_arg(data),
+                )
+                .returns::<[u8; 4]>()
+                .try_invoke();
+
+            match result {
+                Ok(Ok(selector)) if selector == ON_ERC721_RECEIVED_SELECTOR => Ok(()),
+                _ => Err(Error::ReceiverRejected),
+            }
+        }
+
+        // 売上のうち寄付分を別に計上する
+        fn record_revenue(&mut self, amount: Balance) {
+            if self.charity.is_none() {
+                return;
+            }
+            let share = amount.saturating_mul(Balance::from(self.charity_share))
+                / Balance::from(ROYALTY_DENOMINATOR);
+            self.charity_balance = self.charity_balance.saturating_add(share);
+        }
+
+        // ロイヤリティや投げ銭の受け取り先(アーティスト)。未設定ならオーナー
+        fn royalty_receiver(&self) -> AccountId {
+            self.artist().unwrap_or(self.owner)
+        }
+
+        // 期限が指定されていれば過ぎていないか
+        fn ensure_not_expired(&self, valid_until: Option<Timestamp>) -> Result<(), Error> {
+            match valid_until {
+                Some(deadline) if self.env().block_timestamp() > deadline => {
+                    Err(Error::DeadlineExpired)
+                }
+                _ => Ok(()),
+            }
+        }
+
+        // これまでにmintした全範囲のメタデータが変わったことを知らせる。まだmintしていなければ何もしない
+        fn emit_batch_metadata_update(&self) {
+            if self.minted_count == 0 {
+                return;
+            }
+            self.env().emit_event(BatchMetadataUpdate {
+                from_id: self.first_id,
+                to_id: self.token_id - self.id_step,
+            });
+        }
+
+        // 呼び出し元がトークンの所有者か
+        fn ensure_token_owner(&self, id: TokenId) -> Result<(), Error> {
+            if self.owner_or_err(id)? != self.env().caller() {
+                return Err(Error::NotOwner);
+            }
+            Ok(())
+        }
+
+        // 呼び出しもとがコントラクトのオーナーか
+        fn ensure_owner(&self) -> Result<(), Error> {
+            if self.env().caller() != self.owner {
+                return Err(Error::NotOwner);
+            }
+            Ok(())
+        }
+
+        // ランキングを更新する
+        fn update_top_holders(&mut self, account: &AccountId, balance: u64) {
+            let mut top = self.top_holders();
+            let position = top.iter().position(|(a, _)| a == account);
+
+            match position {
+                Some(i) if balance == 0 => {
+                    top.remove(i);
+                }
+                Some(i) => top[i].1 = balance,
+                None if balance == 0 => return,
+                None => {
+                    // 最下位より少なければ載らない
+                    if top.len() >= TOP_HOLDERS_LEN
+                        && top.last().is_some_and(|(_, b)| *b >= balance)
+                    {
+                        return;
+                    }
+                    top.push((*account, balance));
+                }
+            }
+
+            // 同じ所有数なら先に載っていた方を上にする
+            top.sort_by_key(|(_, b)| core::cmp::Reverse(*b));
+            top.truncate(TOP_HOLDERS_LEN);
+            self.top_holders.set(&top);
+        }
+
+        // 1回で扱う件数が上限を超えていないか
+        fn ensure_batch_size(&self, len: u32) -> Result<(), Error> {
+            if len > self.max_batch_size {
+                return Err(Error::BatchTooLarge);
+            }
+            Ok(())
+        }
+
+        // PSP34のIdをTokenIdにする。BytesはNone
+        fn token_id_of(id: &Id) -> Option<TokenId> {
+            match *id {
+                Id::U8(n) => Some(TokenId::from(n)),
+                Id::U16(n) => Some(TokenId::from(n)),
+                Id::U32(n) => Some(TokenId::from(n)),
+                Id::U64(n) => Some(TokenId::from(n)),
+                Id::U128(n) => Some(n),
+                Id::Bytes(_) => None,
+            }
+        }
+    }
+
+    // ERC-721の中心部分はinternalにある。ここではフックで独自の集計を更新する
+    impl Erc721Internal for Erc721 {
+        fn data(&self) -> &internal::Data {
+            &self.erc721
+        }
+
+        fn data_mut(&mut self) -> &mut internal::Data {
+            &mut self.erc721
+        }
+
+        fn after_balance_changed(&mut self, of: &AccountId, before: u64, after: u64) {
+            // 0 -> 1 で保有者が増え、1 -> 0 で減る
+            if before == 0 {
+                self.holder_count += 1;
+            } else if after == 0 {
+                self.holder_count -= 1;
+            }
+            self.update_top_holders(of, after);
+        }
+
+        // 転送では所有者ごとの一覧を付け替える(mint・burnはafter_tokens_added/removedで行う)
+        #[cfg_attr(not(feature = "enumerable"), allow(unused_variables))]
+        fn after_token_transfer(
+            &mut self,
+            from: Option<&AccountId>,
+            to: Option<&AccountId>,
+            id: TokenId,
+        ) {
+            #[cfg(feature = "enumerable")]
+            if let (Some(from), Some(to)) = (from, to) {
+                let last = self.balance_of_or_zero(from);
+                self.remove_from_owner_tokens(from, id, last);
+                let index = self.balance_of_or_zero(to) - 1;
+                self.add_to_owner_tokens(to, id, index);
+            }
+        }
+
+        #[cfg_attr(not(feature = "enumerable"), allow(unused_variables))]
+        fn after_tokens_added(&mut self, to: &AccountId, ids: &[TokenId]) {
+            // 所有者の一覧では追加した分が最後に並ぶ
+            #[cfg(feature = "enumerable")]
+            let first = self.balance_of_or_zero(to) - ids.len() as u64;
+            for (i, id) in ids.iter().enumerate() {
+                self.set_live_bit(*id, true);
+                #[cfg(feature = "enumerable")]
+                self.add_to_owner_tokens(to, *id, first + i as u64);
+            }
+        }
+
+        #[cfg_attr(not(feature = "enumerable"), allow(unused_variables))]
+        fn after_tokens_removed(&mut self, from: &AccountId, ids: &[TokenId]) {
+            // 所有者の一覧は一つずつ縮めていく
+            #[cfg(feature = "enumerable")]
+            let mut len = self.balance_of_or_zero(from) + ids.len() as u64;
+            for id in ids {
+                self.set_live_bit(*id, false);
+                #[cfg(feature = "enumerable")]
+                {
+                    len -= 1;
+                    self.remove_from_owner_tokens(from, *id, len);
+                }
+            }
+        }
+    }
+
+    impl From<Error> for PSP34Error {
+        fn from(error: Error) -> Self {
+            match error {
+                Error::NotOwner | Error::NotApproved => PSP34Error::NotApproved,
+                Error::TokenExists => PSP34Error::TokenExists,
+                Error::TokenNotFound => PSP34Error::TokenNotExists,
+                Error::SelfApproval | Error::ApproveToOwner => PSP34Error::SelfApprove,
+                Error::ReceiverRejected => {
+                    PSP34Error::SafeTransferCheckFailed(String::from("ReceiverRejected"))
+                }
+                other => PSP34Error::Custom(ink::prelude::format!("{:?}", other)),
+            }
+        }
+    }
+
+    // PSP34。既存のERC-721形式のメッセージに処理を任せる
+    impl PSP34 for Erc721 {
+        // コレクションIDはコントラクトのアドレス
+        #[ink(message)]
+        fn collection_id(&self) -> Id {
+            Id::Bytes(<AccountId as AsRef<[u8]>>::as_ref(&self.env().account_id()).to_vec())
+        }
+
+        #[ink(message)]
+        fn balance_of(&self, owner: AccountId) -> u32 {
+            u32::try_from(self.balance_of_or_zero(&owner)).unwrap_or(u32::MAX)
+        }
+
+        #[ink(message)]
+        fn owner_of(&self, id: Id) -> Option<AccountId> {
+            Self::token_id_of(&id).and_then(|id| self.erc721.token_owner.get(id))
+        }
+
+        #[ink(message)]
+        fn allowance(&self, owner: AccountId, operator: AccountId, id: Option<Id>) -> bool {
+            if self.approved_for_all(owner, operator) {
+                return true;
+            }
+            id.as_ref().and_then(Self::token_id_of).is_some_and(|id| {
+                self.erc721.token_owner.get(id) == Some(owner)
+                    && self.erc721.token_approvals.get(id) == Some(operator)
+            })
+        }
+
+        #[ink(message)]
+        fn approve(
+            &mut self,
+            operator: AccountId,
+            id: Option<Id>,
+            approved: bool,
+        ) -> Result<(), PSP34Error> {
+            let Some(id) = id else {
+                return Ok(self.approve_for_all(operator, approved)?);
+            };
+            let id = Self::token_id_of(&id).ok_or(PSP34Error::TokenNotExists)?;
+            if approved {
+                return Ok(self.approve_for(&operator, id)?);
+            }
+
+            // 別のアカウントへのApproveは残す
+            if self.erc721.token_approvals.get(id) != Some(operator) {
+                self.owner_or_err(id)?;
+                return Ok(());
+            }
+            Ok(self.revoke_approval(id)?)
+        }
+
+        // dataは受け取り側に渡す
+        #[ink(message)]
+        fn transfer(&mut self, to: AccountId, id: Id, data: Vec<u8>) -> Result<(), PSP34Error> {
+            let id = Self::token_id_of(&id).ok_or(PSP34Error::TokenNotExists)?;
+            let from = self.owner_or_err(id)?;
+            Ok(self.safe_transfer_from_with_data(from, to, id, data)?)
+        }
+
+        #[ink(message)]
+        fn total_supply(&self) -> u128 {
+            u128::from(self.minted_count - self.burned_count)
+        }
+    }
+
+    // PSP34::metadata。コレクションのIDに対してname/symbol/baseURIを返す
+    impl PSP34Metadata for Erc721 {
+        #[ink(message)]
+        fn get_attribute(&self, id: Id, key: Vec<u8>) -> Option<Vec<u8>> {
+            // コレクション以外のIdならトークンの属性を返す
+            if id != PSP34::collection_id(self) {
+                let id = Self::token_id_of(&id)?;
+                let key = String::from_utf8(key).ok()?;
+                return self.attributes.get((id, key)).map(String::into_bytes);
+            }
+            match key.as_slice() {
+                b"name" => Some(self.name.as_bytes().to_vec()),
+                b"symbol" => Some(self.symbol.as_bytes().to_vec()),
+                b"baseURI" => Some(self.base_uri.as_bytes().to_vec()),
+                _ => None,
+            }
+        }
+    }
+
+    // PSP34::enumerable。IdはU128で返す
+    // ink!はトレイトのimplにcfgを付けられないので、enumerableがなければ常にTokenNotExistsになる
+    impl PSP34Enumerable for Erc721 {
+        #[ink(message)]
+        fn owners_token_by_index(&self, owner: AccountId, index: u128) -> Result<Id, PSP34Error> {
+            if !cfg!(feature = "enumerable") {
+                return Err(PSP34Error::TokenNotExists);
+            }
+            u64::try_from(index)
+                .ok()
+                .and_then(|index| self.owned_tokens.get((owner, index)))
+                .map(Id::U128)
+                .ok_or(PSP34Error::TokenNotExists)
+        }
+
+        #[ink(message)]
+        fn token_by_index(&self, index: u128) -> Result<Id, PSP34Error> {
+            if !cfg!(feature = "enumerable") {
+                return Err(PSP34Error::TokenNotExists);
+            }
+            u64::try_from(index)
+                .ok()
+                .and_then(|index| self.all_tokens.get(index))
+                .map(Id::U128)
+                .ok_or(PSP34Error::TokenNotExists)
+        }
+    }
+
+    // ベンチマーク用のメッセージ。benchフィーチャーを有効にしたときだけコンパイルされる
+    // ink!はimplブロックのcfgを見てくれないので各メッセージにcfgをつける
+    // gasの計測はオフチェーン環境では使えないのでノードに対して実行すること
+    impl Erc721 {
+        // mintのgasとストレージ増減を計測する。mint_price分を添えて呼ぶ
+        #[cfg(feature = "bench")]
+        #[ink(message, payable)]
+        pub fn bench_mint(&mut self) -> Result<BenchReport, Error> {
+            let caller = self.env().caller();
+            let id = self.token_id;
+            self.bench(&[caller], &[id], |erc721| {
+                erc721.mint(None, 1, None).map(|_| ())
+            })
+        }
+
+        // transferのgasとストレージ増減を計測する
+        #[cfg(feature = "bench")]
+        #[ink(message)]
+        pub fn bench_transfer(
+            &mut self,
+            destination: AccountId,
+            id: TokenId,
+        ) -> Result<BenchReport, Error> {
+            let caller = self.env().caller();
+            self.bench(&[caller, destination], &[id], |erc721| {
+                erc721.transfer(destination, id, None, None)
+            })
+        }
+
+        // 連続mintのgasとストレージ増減を計測する。n個分のmint_priceを添えて呼ぶ
+        #[cfg(feature = "bench")]
+        #[ink(message, payable)]
+        pub fn bench_mint_batch(&mut self, n: u32) -> Result<BenchReport, Error> {
+            self.ensure_batch_size(n)?;
+            let caller = self.env().caller();
+            let ids: Vec<TokenId> = (0..n)
+                .map(|i| {
+                    self.token_id
+                        .saturating_add(TokenId::from(i).saturating_mul(self.id_step))
+                })
+                .collect();
+            self.bench(&[caller], &ids, |erc721| erc721.mint_many(n).map(|_| ()))
+        }
+
+        #[cfg(feature = "bench")]
+        fn bench<F>(
+            &mut self,
+            accounts: &[AccountId],
+            ids: &[TokenId],
+            f: F,
+        ) -> Result<BenchReport, Error>
+        where
+            F: FnOnce(&mut Self) -> Result<(), Error>,
+        {
+            let storage_before = self.storage_size_of(accounts, ids);
+            let gas_before = self.env().gas_left();
+            f(self)?;
+            let gas_after = self.env().gas_left();
+            let storage_after = self.storage_size_of(accounts, ids);
+
+            Ok(BenchReport {
+                gas: gas_before.saturating_sub(gas_after),
+                storage_bytes: storage_after - storage_before,
+            })
+        }
+
+        // 指定のアカウントとトークンに関するストレージのバイト数
+        #[cfg(feature = "bench")]
+        fn storage_size_of(&self, accounts: &[AccountId], ids: &[TokenId]) -> i64 {
+            let account_bytes: u32 = accounts
+                .iter()
+                .map(|a| self.erc721.owned_tokens_count.size(a).unwrap_or(0))
+                .sum();
+            let token_bytes: u32 = ids.iter().map(|id| self.token_storage_size(*id)).sum();
+            // 生存ビットマップはトークン間で共有するので1エントリ1回だけ数える
+            let mut words: Vec<TokenId> = ids.iter().map(|id| id / 256).collect();
+            words.dedup();
+            let bitmap_bytes: u32 = words
+                .iter()
+                .map(|word| self.live_bitmap.size(word).unwrap_or(0))
+                .sum();
+            i64::from(account_bytes) + i64::from(token_bytes) + i64::from(bitmap_bytes)
+        }
+
+        // トークンごとのストレージのバイト数(clear_token_dataで消すものと所有者・一覧)
+        #[cfg(feature = "bench")]
+        fn token_storage_size(&self, id: TokenId) -> u32 {
+            let mut size = [
+                self.erc721.token_owner.size(id),
+                self.erc721.token_approvals.size(id),
+                self.token_notes.size(id),
+                self.token_names.size(id),
+                self.token_descriptions.size(id),
+                self.transfer_counts.size(id),
+                self.token_timestamps.size(id),
+                self.minters.size(id),
+                self.uri_overrides.size(id),
+                self.token_uris.size(id),
+                self.external_urls.size(id),
+                self.animation_urls.size(id),
+                self.artist_signatures.size(id),
+                self.rerolled.size(id),
+                self.token_palettes.size(id),
+                self.seeds.size(id),
+                self.seed_sources.size(id),
+                self.token_editions.size(id),
+                self.frozen_metadata.size(id),
+                self.attribute_keys.size(id),
+                self.all_tokens_index.size(id),
+                self.owned_tokens_index.size(id),
+            ]
+            .into_iter()
+            .map(|bytes| bytes.unwrap_or(0))
+            .sum::<u32>();
+
+            for key in self.attribute_keys_of(id) {
+                size += self.attributes.size((id, key.clone())).unwrap_or(0);
+                size += self
+                    .attribute_tokens_index
+                    .size((id, key.as_str()))
+                    .unwrap_or(0);
+                if let (Some(value), Some(index)) = (
+                    self.attributes.get((id, key.clone())),
+                    self.attribute_tokens_index.get((id, key.as_str())),
+                ) {
+                    size += self
+                        .attribute_tokens
+                        .size((key.as_str(), value.as_str(), index))
+                        .unwrap_or(0);
+                }
+            }
+            if let Some(index) = self.all_tokens_index.get(id) {
+                size += self.all_tokens.size(index).unwrap_or(0);
+            }
+            if let (Some(owner), Some(index)) = (
+                self.erc721.token_owner.get(id),
+                self.owned_tokens_index.get(id),
+            ) {
+                size += self.owned_tokens.size((owner, index)).unwrap_or(0);
+            }
+            size
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        thread_local! {
+            // gas_leftの代わりに返す値
+            pub(super) static GAS_LEFT: core::cell::Cell<u64> = const { core::cell::Cell::new(u64::MAX) };
+        }
+
+        type Event = <Erc721 as ink::reflect::ContractEventBase>::Type;
+
+        // randomnessフィーチャーではmintに乱数のエクステンションが必要
+        fn register_mock_random() {
+            #[cfg(feature = "randomness")]
+            ink::env::test::register_chain_extension(MockRandom { fail: false });
+        }
+
+        // テストで使う上限。fixed-supplyのときはビルド時の上限でしか作れない
+        #[cfg(feature = "fixed-supply")]
+        const MAX_SUPPLY: TokenId = FIXED_MAX_SUPPLY;
+        #[cfg(not(feature = "fixed-supply"))]
+        const MAX_SUPPLY: TokenId = 100;
+
+        fn new_erc721() -> Erc721 {
+            register_mock_random();
+            Erc721::new(
+                String::from("p5js"),
+                String::from("P5"),
+                String::from("https://example.com/"),
+                MAX_SUPPLY,
+                0,
+                500,
+                1,
+                1,
+            )
+            .unwrap()
+        }
+
+        #[ink::test]
+        fn new_rejects_invalid_parameters() {
+            let new = |name: &str, base_uri: &str, max_supply, royalty| {
+                Erc721::new(
+                    String::from(name),
+                    String::from("P5"),
+                    String::from(base_uri),
+                    max_supply,
+                    0,
+                    royalty,
+                    1,
+                    1,
+                )
+                .map(|_| ())
+            };
+            assert_eq!(
+                new("", "https://example.com/", MAX_SUPPLY, 500),
+                Err(Error::InvalidParameter)
+            );
+            assert_eq!(
+                new("p5js", "", MAX_SUPPLY, 500),
+                Err(Error::InvalidParameter)
+            );
+            assert_eq!(
+                new("p5js", "https://example.com/", 0, 500),
+                Err(Error::InvalidParameter)
+            );
+            assert_eq!(
+                new("p5js", "https://example.com/", MAX_SUPPLY, 10_001),
+                Err(Error::InvalidParameter)
+            );
+            assert_eq!(
+                new("p5js", "https://example.com/", MAX_SUPPLY, 10_000),
+                Ok(())
+            );
+        }
+
+        #[ink::test]
+        fn name_and_symbol_work() {
+            let erc721 = new_erc721();
+            assert_eq!(erc721.name(), "p5js");
+            assert_eq!(erc721.symbol(), "P5");
+        }
+
+        // fixed-supplyのときは上限を変えられない
+        #[cfg(not(feature = "fixed-supply"))]
+        #[ink::test]
+        fn mint_respects_max_supply() {
+            register_mock_random();
+            let mut erc721 = Erc721::new(
+                String::from("p5js"),
+                String::from("P5"),
+                String::from("https://example.com/"),
+                1,
+                0,
+                0,
+                1,
+                1,
+            )
+            .unwrap();
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.mint(None, 1, None), Err(Error::MaxSupplyReached));
+        }
+
+        #[cfg(feature = "fixed-supply")]
+        #[ink::test]
+        fn fixed_supply_caps_mint() {
+            let mut erc721 = new_erc721();
+            assert_eq!(erc721.max_supply(), FIXED_MAX_SUPPLY);
+            assert_eq!(
+                Erc721::new(
+                    String::from("p5js"),
+                    String::from("P5"),
+                    String::from("https://example.com/"),
+                    FIXED_MAX_SUPPLY + 1,
+                    0,
+                    0,
+                    1,
+                    1,
+                )
+                .err(),
+                Some(Error::InvalidParameter)
+            );
+
+            // 上限の1つ手前まで発行済みにする
+            erc721.minted_count = u64::try_from(FIXED_MAX_SUPPLY - 1).unwrap();
+            assert_eq!(erc721.mint(None, 2, None), Err(Error::MaxSupplyReached));
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.mint(None, 1, None), Err(Error::MaxSupplyReached));
+        }
+
+        #[ink::test]
+        fn token_id_start_and_step_work() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            register_mock_random();
+            let mut erc721 = Erc721::new(
+                String::from("p5js"),
+                String::from("P5"),
+                String::from("https://example.com/"),
+                MAX_SUPPLY,
+                0,
+                0,
+                1000,
+                10,
+            )
+            .unwrap();
+            assert_eq!(erc721.mint(None, 1, None), Ok(vec![1000]));
+            assert_eq!(erc721.mint(None, 1, None), Ok(vec![1010]));
+            assert_eq!(erc721.owner_of(1000), Some(accounts.alice));
+            assert_eq!(erc721.owner_of(1010), Some(accounts.alice));
+            assert_eq!(erc721.owner_of(1001), None);
+            assert_eq!(erc721.total_supply(), 2);
+            assert_eq!(
+                erc721.token_uri(1010),
+                Ok(String::from("https://example.com/1010"))
+            );
+        }
+
+        #[ink::test]
+        fn mint_rejects_token_id_overflow() {
+            register_mock_random();
+            let mut erc721 = Erc721::new(
+                String::from("p5js"),
+                String::from("P5"),
+                String::from("https://example.com/"),
+                MAX_SUPPLY,
+                0,
+                0,
+                TokenId::MAX - 15,
+                10,
+            )
+            .unwrap();
+            // 2つ目のIDは作れるが、その次のIDが桁あふれするので何もmintされない
+            assert_eq!(erc721.mint(None, 2, None), Err(Error::Overflow));
+            assert_eq!(erc721.total_supply(), 0);
+            assert_eq!(erc721.mint(None, 1, None), Ok(vec![TokenId::MAX - 15]));
+            assert_eq!(erc721.mint(None, 1, None), Err(Error::Overflow));
+            assert_eq!(erc721.total_supply(), 1);
+        }
+
+        #[ink::test]
+        fn mint_requires_payment() {
+            register_mock_random();
+            let mut erc721 = Erc721::new(
+                String::from("p5js"),
+                String::from("P5"),
+                String::from("https://example.com/"),
+                MAX_SUPPLY,
+                10,
+                0,
+                1,
+                1,
+            )
+            .unwrap();
+            assert_eq!(erc721.mint(None, 1, None), Err(Error::InsufficientPayment));
+            ink::env::test::set_value_transferred::<ink::env::DefaultEnvironment>(11);
+            assert_eq!(erc721.mint(None, 1, None), Err(Error::UnexpectedValue));
+            ink::env::test::set_value_transferred::<ink::env::DefaultEnvironment>(10);
+            assert!(erc721.mint(None, 1, None).is_ok());
+        }
+
+        #[ink::test]
+        fn mint_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+
+            // まだトークンがmintされていないので所有者はいない
+            assert_eq!(erc721.owner_of(1), None);
+            // デフォルトユーザーでまだmintしていないのでトークンをもっていない
+            assert_eq!(erc721.balance_of(accounts.alice), 0);
+            // mint成功するはず
+            assert_eq!(erc721.mint(None, 1, None), Ok(vec![1]));
+            // mintしたのでトークンを所有しているはず
+            assert_eq!(erc721.balance_of(accounts.alice), 1);
+        }
+
+        #[test]
+        fn parse_id_works() {
+            assert_eq!(parse_id("0"), 0);
+            assert_eq!(parse_id("10000"), 10000);
+            assert_eq!(
+                parse_id("340282366920938463463374607431768211455"),
+                TokenId::MAX
+            );
+        }
+
+        #[ink::test]
+        fn token_uri_works() {
+            let mut erc721 = new_erc721();
+            assert_eq!(erc721.token_uri(1), Err(Error::TokenNotFound));
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(
+                erc721.token_uri(1),
+                Ok(String::from("https://example.com/1"))
+            );
+            assert_eq!(
+                erc721.token_uri(2),
+                Ok(String::from("https://example.com/2"))
+            );
+
+            // burnしたトークンのURIはない
+            assert_eq!(erc721.burn(2), Ok(()));
+            assert_eq!(erc721.token_uri(2), Err(Error::TokenNotFound));
+        }
+
+        #[ink::test]
+        fn set_base_uri_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            assert_eq!(
+                erc721.set_base_uri(String::new()),
+                Err(Error::InvalidParameter)
+            );
+            assert_eq!(erc721.set_base_uri(String::from("ipfs://cid/")), Ok(()));
+            assert_eq!(erc721.token_uri(1), Ok(String::from("ipfs://cid/1")));
+            // Transfer, BaseUriChanged, BatchMetadataUpdate
+            assert_eq!(ink::env::test::recorded_events().count(), 3);
+
+            // オーナー以外は変更できない
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(
+                erc721.set_base_uri(String::from("https://example.com/")),
+                Err(Error::NotOwner)
+            );
+        }
+
+        #[ink::test]
+        fn contract_uri_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert_eq!(erc721.contract_uri(), "");
+
+            let uri = String::from("https://example.com/collection.json");
+            assert_eq!(erc721.set_contract_uri(uri.clone()), Ok(()));
+            assert_eq!(erc721.contract_uri(), uri);
+
+            // オーナー以外は変更できない
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.set_contract_uri(String::new()), Err(Error::NotOwner));
+        }
+
+        #[ink::test]
+        fn estimate_mint_deposit_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            // トークンごとに所有者とmint者(32バイトずつ)、mint時刻(16バイト)、シード(32バイト)、
+            // シードの材料(最大69バイト)。enumerableなら一覧と位置(16バイトと8バイトが2つずつ)
+            let (items, bytes) = if cfg!(feature = "enumerable") {
+                (9, 229)
+            } else {
+                (5, 181)
+            };
+
+            // 生存ビットマップ(32バイト)と所有数(8バイト)も増える
+            assert_eq!(
+                erc721.estimate_mint_deposit(accounts.alice),
+                Ok(StorageEstimate {
+                    items: items + 2,
+                    bytes: bytes + 40
+                })
+            );
+            assert_eq!(
+                erc721.estimate_mint_many_deposit(accounts.alice, 3),
+                Ok(StorageEstimate {
+                    items: items * 3 + 2,
+                    bytes: bytes * 3 + 40
+                })
+            );
+            assert_eq!(
+                erc721.estimate_mint_many_deposit(accounts.alice, DEFAULT_MAX_BATCH_SIZE + 1),
+                Err(Error::BatchTooLarge)
+            );
+
+            // パレット(4バイト)と属性のキー一覧(7バイト)、属性1つにつき値(最大5バイト)と
+            // 属性ごとの一覧(16バイト)とその位置(4バイト)。まだ誰も持っていない値は数(4バイト)も増える
+            assert_eq!(
+                erc721.set_rarity_table(
+                    String::from("color"),
+                    vec![(String::from("red"), 1), (String::from("blue"), 1)]
+                ),
+                Ok(())
+            );
+            assert_eq!(
+                erc721.add_palette(String::from("mono"), vec![[0, 0, 0]]),
+                Ok(0)
+            );
+            let (items, bytes) = (items + 5, bytes + 36);
+            assert_eq!(
+                erc721.estimate_mint_deposit(accounts.alice),
+                Ok(StorageEstimate {
+                    items: items + 3,
+                    bytes: bytes + 44
+                })
+            );
+            assert_eq!(
+                erc721.estimate_mint_many_deposit(accounts.alice, 3),
+                Ok(StorageEstimate {
+                    items: items * 3 + 4,
+                    bytes: bytes * 3 + 48
+                })
+            );
+
+            // 所有数とビットマップのエントリは既にあり、値1つの数もある
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(
+                erc721.estimate_mint_deposit(accounts.alice),
+                Ok(StorageEstimate {
+                    items: items + 1,
+                    bytes: bytes + 4
+                })
+            );
+            assert_eq!(
+                erc721.estimate_mint_many_deposit(accounts.alice, 3),
+                Ok(StorageEstimate {
+                    items: items * 3 + 1,
+                    bytes: bytes * 3 + 4
+                })
+            );
+        }
+
+        // 見積もりは最大のエントロピーでmintしたときの実際のストレージと一致する
+        #[cfg(feature = "bench")]
+        #[ink::test]
+        fn estimate_matches_storage_size() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert_eq!(
+                erc721.set_rarity_table(String::from("color"), vec![(String::from("red"), 1)]),
+                Ok(())
+            );
+            assert_eq!(
+                erc721.add_palette(String::from("mono"), vec![[0, 0, 0]]),
+                Ok(0)
+            );
+            let estimate = erc721.estimate_mint_deposit(accounts.alice).unwrap();
+            let ids = [erc721.token_id];
+            let before = erc721.storage_size_of(&[accounts.alice], &ids);
+            assert!(erc721.mint(None, 1, Some(vec![0; MAX_ENTROPY_LEN])).is_ok());
+            let after = erc721.storage_size_of(&[accounts.alice], &ids);
+            // 値の数のエントリ(4バイト)はトークンごとではないのでstorage_size_ofには入らない
+            assert_eq!(after - before, i64::from(estimate.bytes) - 4);
+        }
+
+        #[ink::test]
+        fn batch_mint_stops_before_running_out_of_gas() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            register_mock_random();
+            let mut erc721 = Erc721::new(
+                String::from("p5js"),
+                String::from("P5"),
+                String::from("https://example.com/"),
+                MAX_SUPPLY,
+                10,
+                0,
+                1,
+                1,
+            )
+            .unwrap();
+            let contract = ink::env::account_id::<ink::env::DefaultEnvironment>();
+            ink::env::test::set_account_balance::<ink::env::DefaultEnvironment>(contract, 1000);
+            let truncated = || {
+                ink::env::test::recorded_events()
+                    .filter_map(|event| {
+                        match <Event as scale::Decode>::decode(&mut &event.data[..]).unwrap() {
+                            Event::BatchMintTruncated(e) => {
+                                Some((e.minted, e.remaining, e.next_id))
+                            }
+                            _ => None,
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            // 2個分のgasしかなければ2個で止めて、残りの代金を返す
+            GAS_LEFT.with(|gas| gas.set(MINT_GAS_RESERVE + 2 * MINT_GAS_PER_TOKEN));
+            let before =
+                ink::env::test::get_account_balance::<ink::env::DefaultEnvironment>(accounts.alice)
+                    .unwrap();
+            ink::env::test::set_value_transferred::<ink::env::DefaultEnvironment>(50);
+            assert_eq!(erc721.mint_many(5), Ok(vec![1, 2]));
+            assert_eq!(
+                ink::env::test::get_account_balance::<ink::env::DefaultEnvironment>(accounts.alice),
+                Ok(before + 30)
+            );
+            assert_eq!(truncated(), vec![(2, 3, 3)]);
+
+            // 無料の配布も同じ。gasがほとんどなくても1個はmintする
+            ink::env::test::set_value_transferred::<ink::env::DefaultEnvironment>(0);
+            GAS_LEFT.with(|gas| gas.set(0));
+            assert_eq!(erc721.mint_many_to(accounts.bob, 3), Ok(vec![3]));
+            assert_eq!(truncated().last(), Some(&(1, 2, 4)));
+
+            // エディションは途中で止めない
+            assert_eq!(erc721.mint_edition(1, 3), Ok(vec![4, 5, 6]));
+            assert_eq!(truncated().len(), 2);
+
+            // gasが足りれば全部mintしてイベントも出ない
+            GAS_LEFT.with(|gas| gas.set(u64::MAX));
+            assert_eq!(erc721.mint_many_to(accounts.bob, 3), Ok(vec![7, 8, 9]));
+            assert_eq!(truncated().len(), 2);
+        }
+
+        #[ink::test]
+        fn set_max_batch_size_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+
+            assert_eq!(erc721.set_max_batch_size(0), Err(Error::InvalidParameter));
+            assert_eq!(erc721.set_max_batch_size(2), Ok(()));
+            assert_eq!(erc721.max_batch_size(), 2);
+            assert_eq!(
+                erc721.estimate_mint_many_deposit(accounts.alice, 3),
+                Err(Error::BatchTooLarge)
+            );
+
+            // オーナー以外は変更できない
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.set_max_batch_size(10), Err(Error::NotOwner));
+        }
+
+        #[ink::test]
+        fn charity_split_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            register_mock_random();
+            let mut erc721 = Erc721::new(
+                String::from("p5js"),
+                String::from("P5"),
+                String::from("https://example.com/"),
+                MAX_SUPPLY,
+                200,
+                500,
+                1,
+                1,
+            )
+            .unwrap();
+            assert_eq!(erc721.set_charity(None, 100), Err(Error::InvalidParameter));
+            // 売上の10%を寄付
+            assert_eq!(erc721.set_charity(Some(accounts.eve), 1_000), Ok(()));
+
+            let contract = ink::env::account_id::<ink::env::DefaultEnvironment>();
+            ink::env::test::set_account_balance::<ink::env::DefaultEnvironment>(contract, 200);
+            ink::env::test::set_value_transferred::<ink::env::DefaultEnvironment>(200);
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.charity_balance(), 20);
+
+            // 寄付分はオーナーでも引き出せない
+            assert_eq!(
+                erc721.withdraw(accounts.alice, 200),
+                Err(Error::InsufficientBalance)
+            );
+            assert_eq!(erc721.withdraw(accounts.alice, 180), Ok(()));
+
+            let before =
+                ink::env::test::get_account_balance::<ink::env::DefaultEnvironment>(accounts.eve)
+                    .unwrap();
+            assert_eq!(erc721.pay_charity(), Ok(()));
+            assert_eq!(erc721.charity_balance(), 0);
+            assert_eq!(erc721.charity_donated(), 20);
+            assert_eq!(
+                ink::env::test::get_account_balance::<ink::env::DefaultEnvironment>(accounts.eve),
+                Ok(before + 20)
+            );
+        }
+
+        #[ink::test]
+        fn set_charity_keeps_owed_balance() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            register_mock_random();
+            let mut erc721 = Erc721::new(
+                String::from("p5js"),
+                String::from("P5"),
+                String::from("https://example.com/"),
+                MAX_SUPPLY,
+                200,
+                500,
+                1,
+                1,
+            )
+            .unwrap();
+            assert_eq!(erc721.set_charity(Some(accounts.eve), 1_000), Ok(()));
+            ink::env::test::set_value_transferred::<ink::env::DefaultEnvironment>(200);
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.charity_balance(), 20);
+
+            // 寄付先を変えるときは送金しないので、送金の失敗で変更できなくなることはない
+            let before =
+                ink::env::test::get_account_balance::<ink::env::DefaultEnvironment>(accounts.eve)
+                    .unwrap();
+            assert_eq!(erc721.set_charity(Some(accounts.frank), 500), Ok(()));
+            assert_eq!(erc721.charity(), Some(accounts.frank));
+            assert_eq!(erc721.charity_balance(), 0);
+            assert_eq!(erc721.charity_owed(accounts.eve), 20);
+            assert_eq!(
+                ink::env::test::get_account_balance::<ink::env::DefaultEnvironment>(accounts.eve),
+                Ok(before)
+            );
+
+            // 前の寄付先の分もオーナーは引き出せない
+            let contract = ink::env::account_id::<ink::env::DefaultEnvironment>();
+            ink::env::test::set_account_balance::<ink::env::DefaultEnvironment>(contract, 200);
+            assert_eq!(
+                erc721.withdraw(accounts.alice, 181),
+                Err(Error::InsufficientBalance)
+            );
+
+            // 前の寄付先には誰でも送金できる
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.pay_charity_owed(accounts.eve), Ok(()));
+            assert_eq!(
+                ink::env::test::get_account_balance::<ink::env::DefaultEnvironment>(accounts.eve),
+                Ok(before + 20)
+            );
+            assert_eq!(erc721.charity_owed(accounts.eve), 0);
+            assert_eq!(erc721.charity_donated(), 20);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(erc721.withdraw(accounts.alice, 180), Ok(()));
+        }
+
+        #[ink::test]
+        fn override_token_uri_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            let notice = String::from("https://example.com/takedown");
+            assert_eq!(
+                erc721.override_token_uri(1, notice.clone()),
+                Err(Error::NotModerator)
+            );
+
+            assert_eq!(erc721.set_moderator(accounts.bob, true), Ok(()));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(
+                erc721.override_token_uri(2, notice.clone()),
+                Err(Error::TokenNotFound)
+            );
+            assert_eq!(erc721.override_token_uri(1, notice.clone()), Ok(()));
+            assert!(erc721.is_token_uri_overridden(1));
+            assert_eq!(erc721.token_uri_override(1), Some(notice.clone()));
+            assert_eq!(erc721.token_uri(1), Ok(notice));
+
+            assert_eq!(erc721.clear_token_uri_override(1), Ok(()));
+            assert!(!erc721.is_token_uri_overridden(1));
+            assert_eq!(
+                erc721.token_uri(1),
+                Ok(String::from("https://example.com/1"))
+            );
+        }
+
+        #[ink::test]
+        fn notes_work() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            let note = String::from("exhibited at X, 2025");
+            assert_eq!(erc721.add_note(1, note.clone()), Ok(()));
+            assert_eq!(
+                erc721.add_note(1, "a".repeat(MAX_NOTE_LEN + 1)),
+                Err(Error::NoteTooLong)
+            );
+            assert_eq!(
+                erc721.add_note(1, String::new()),
+                Err(Error::InvalidParameter)
+            );
+
+            // 転送してもメモは残り、新しい所有者が書ける
+            assert_eq!(erc721.transfer(accounts.bob, 1, None, None), Ok(()));
+            assert_eq!(erc721.add_note(1, note.clone()), Err(Error::NotOwner));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            for _ in 1..MAX_NOTES {
+                assert_eq!(erc721.add_note(1, note.clone()), Ok(()));
+            }
+            assert_eq!(erc721.add_note(1, note.clone()), Err(Error::TooManyNotes));
+            assert_eq!(erc721.notes_of(1).len(), MAX_NOTES);
+
+            assert_eq!(erc721.burn(1), Ok(()));
+            assert_eq!(erc721.notes_of(1), Vec::<String>::new());
+        }
+
+        #[ink::test]
+        fn reveal_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 2, None).is_ok());
+            let placeholder = String::from("https://example.com/hidden.json");
+
+            assert_eq!(erc721.set_placeholder_uri(placeholder.clone()), Ok(()));
+            assert_eq!(
+                erc721.set_token_uri(2, String::from("ipfs://special")),
+                Ok(())
+            );
+            assert!(!erc721.is_revealed());
+            assert_eq!(erc721.token_uri(1), Ok(placeholder.clone()));
+            assert_eq!(erc721.token_uri(2), Ok(placeholder));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.reveal(None, false), Err(Error::NotOwner));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(
+                erc721.reveal(Some(String::new()), false),
+                Err(Error::InvalidParameter)
+            );
+            assert_eq!(
+                erc721.reveal(Some(String::from("https://example.com/revealed/")), false),
+                Ok(())
+            );
+            assert!(erc721.is_revealed());
+            assert_eq!(
+                erc721.token_uri(1),
+                Ok(String::from("https://example.com/revealed/1"))
+            );
+            assert_eq!(erc721.token_uri(2), Ok(String::from("ipfs://special")));
+
+            assert_eq!(erc721.reveal(None, false), Err(Error::AlreadyRevealed));
+            assert_eq!(
+                erc721.set_placeholder_uri(String::new()),
+                Err(Error::AlreadyRevealed)
+            );
+        }
+
+        #[ink::test]
+        fn reveal_offset_works() {
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 2, None).is_ok());
+            assert_eq!(erc721.reveal(None, true), Ok(()));
+
+            let offset = ink::env::test::recorded_events()
+                .find_map(|event| {
+                    match <Event as scale::Decode>::decode(&mut &event.data[..]).unwrap() {
+                        Event::Revealed(Revealed { offset, .. }) => Some(offset),
+                        _ => None,
+                    }
+                })
+                .expect("expected Revealed");
+            assert!(offset < MAX_SUPPLY);
+
+            // 1から上限までを折り返してずらす
+            for id in [1, 2] {
+                assert_eq!(
+                    erc721.token_uri(id),
+                    Ok(format!(
+                        "https://example.com/{}",
+                        (id - 1 + offset) % MAX_SUPPLY + 1
+                    ))
+                );
+            }
+            erc721.reveal_offset = MAX_SUPPLY - 1;
+            assert_eq!(erc721.metadata_id(1), MAX_SUPPLY);
+            assert_eq!(erc721.metadata_id(2), 1);
+        }
+
+        #[ink::test]
+        fn freeze_token_metadata_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 2, None).is_ok());
+            assert_eq!(erc721.transfer(accounts.bob, 2, None, None), Ok(()));
+            let key = || String::from("palette");
+            assert_eq!(erc721.set_attribute(1, key(), String::from("warm")), Ok(()));
+
+            // リビール前は固定できない
+            assert_eq!(
+                erc721.set_placeholder_uri(String::from("https://example.com/hidden")),
+                Ok(())
+            );
+            assert_eq!(erc721.freeze_token_metadata(1), Err(Error::NotRevealed));
+            assert_eq!(erc721.reveal(None, false), Ok(()));
+
+            // トークンの所有者も固定できる
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.charlie);
+            assert_eq!(erc721.freeze_token_metadata(2), Err(Error::NotOwner));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.freeze_token_metadata(2), Ok(()));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(erc721.freeze_token_metadata(1), Ok(()));
+            assert_eq!(erc721.freeze_token_metadata(1), Err(Error::MetadataFrozen));
+            assert!(erc721.is_metadata_frozen(1));
+            assert!(!erc721.is_metadata_frozen(3));
+
+            assert_eq!(
+                erc721.set_attribute(1, key(), String::from("cool")),
+                Err(Error::MetadataFrozen)
+            );
+            assert_eq!(
+                erc721.remove_attribute(1, key()),
+                Err(Error::MetadataFrozen)
+            );
+            assert_eq!(
+                erc721.set_token_uri(1, String::from("ipfs://other")),
+                Err(Error::MetadataFrozen)
+            );
+            assert_eq!(erc721.clear_token_uri(1), Err(Error::MetadataFrozen));
+
+            // ベースURIを変えても固定したURIのまま
+            assert_eq!(
+                erc721.set_base_uri(String::from("https://example.org/")),
+                Ok(())
+            );
+            assert_eq!(
+                erc721.token_uri(1),
+                Ok(String::from("https://example.com/1"))
+            );
+            assert_eq!(erc721.get_attribute(1, key()), Some(String::from("warm")));
+
+            assert_eq!(erc721.burn(1), Ok(()));
+            assert!(!erc721.is_metadata_frozen(1));
+        }
+
+        #[ink::test]
+        fn frozen_token_ignores_later_placeholder() {
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 2, None).is_ok());
+            // プレースホルダーがなければリビール前でも固定できる
+            assert_eq!(erc721.freeze_token_metadata(1), Ok(()));
+
+            let placeholder = String::from("https://example.com/hidden");
+            assert_eq!(erc721.set_placeholder_uri(placeholder.clone()), Ok(()));
+            assert_eq!(
+                erc721.token_uri(1),
+                Ok(String::from("https://example.com/1"))
+            );
+            assert_eq!(erc721.token_uri(2), Ok(placeholder));
+        }
+
+        #[ink::test]
+        fn freeze_all_metadata_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 3, None).is_ok());
+            assert_eq!(erc721.burn(2), Ok(()));
+            assert_eq!(
+                erc721.set_token_uri(3, String::from("ipfs://special")),
+                Ok(())
+            );
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.freeze_all_metadata(1, 2), Err(Error::NotOwner));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(
+                erc721.freeze_all_metadata(1, 0),
+                Err(Error::InvalidParameter)
+            );
+            // 最初のページで固定され、続きのstartが返る
+            assert_eq!(erc721.freeze_all_metadata(1, 2), Ok(Some(3)));
+            assert!(erc721.is_all_metadata_frozen());
+            assert!(erc721.is_metadata_frozen(1));
+            assert!(erc721.is_metadata_frozen(3));
+            assert_eq!(erc721.freeze_all_metadata(3, 2), Ok(None));
+
+            // 残っているトークンごとにPermanentURIが出る
+            let permanent = ink::env::test::recorded_events()
+                .filter_map(|event| {
+                    match <Event as scale::Decode>::decode(&mut &event.data[..]).unwrap() {
+                        Event::PermanentURI(PermanentURI { value, id }) => Some((id, value)),
+                        _ => None,
+                    }
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                permanent,
+                vec![
+                    (1, String::from("https://example.com/1")),
+                    (3, String::from("ipfs://special"))
+                ]
+            );
+
+            let frozen = Err(Error::MetadataFrozen);
+            assert_eq!(
+                erc721.set_base_uri(String::from("https://example.org/")),
+                frozen
+            );
+            assert_eq!(erc721.set_base_cid(String::new()), frozen);
+            assert_eq!(erc721.set_placeholder_uri(String::new()), frozen);
+            assert_eq!(erc721.reveal(None, true), frozen);
+            assert_eq!(
+                erc721.set_token_uri(1, String::from("ipfs://other")),
+                frozen
+            );
+            assert_eq!(
+                erc721.set_attribute(1, String::from("palette"), String::from("warm")),
+                frozen
+            );
+            assert_eq!(erc721.remove_attribute(1, String::from("palette")), frozen);
+            assert_eq!(erc721.freeze_token_metadata(1), frozen);
+            assert_eq!(erc721.upload_script_chunk(0, b"draw".to_vec()), frozen);
+            assert_eq!(erc721.clear_script(), frozen);
+        }
+
+        #[ink::test]
+        fn base_cid_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+            let cid = String::from("QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG");
+
+            assert_eq!(
+                erc721.set_base_cid(String::from("Qm123")),
+                Err(Error::InvalidCid)
+            );
+            assert_eq!(
+                erc721.set_base_cid(format!("{}/", &cid[1..])),
+                Err(Error::InvalidCid)
+            );
+            assert_eq!(erc721.set_base_cid(cid.clone()), Ok(()));
+            assert_eq!(erc721.base_cid(), cid);
+            assert_eq!(erc721.token_uri(1), Ok(format!("ipfs://{cid}/1.json")));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.set_base_cid(String::new()), Err(Error::NotOwner));
+
+            // 空にするとベースURIに戻る
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(erc721.set_base_cid(String::new()), Ok(()));
+            assert_eq!(
+                erc721.token_uri(1),
+                Ok(String::from("https://example.com/1"))
+            );
+        }
+
+        #[ink::test]
+        fn set_token_uri_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 2, None).is_ok());
+            let special = String::from("ipfs://special");
+
+            assert_eq!(
+                erc721.set_token_uri(3, special.clone()),
+                Err(Error::TokenNotFound)
+            );
+            assert_eq!(
+                erc721.set_token_uri(1, String::new()),
+                Err(Error::InvalidParameter)
+            );
+            assert_eq!(erc721.set_token_uri(1, special.clone()), Ok(()));
+            assert_eq!(erc721.token_uri(1), Ok(special.clone()));
+            assert_eq!(
+                erc721.token_uri(2),
+                Ok(String::from("https://example.com/2"))
+            );
+
+            // オーナーとモデレーター以外は指定できない
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(
+                erc721.set_token_uri(2, special.clone()),
+                Err(Error::NotOwner)
+            );
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(erc721.set_moderator(accounts.bob, true), Ok(()));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.set_token_uri(2, special.clone()), Ok(()));
+
+            // モデレーターの差し替えの方が優先される
+            let notice = String::from("https://example.com/removed");
+            assert_eq!(erc721.override_token_uri(1, notice.clone()), Ok(()));
+            assert_eq!(erc721.token_uri(1), Ok(notice));
+            assert_eq!(erc721.clear_token_uri_override(1), Ok(()));
+            assert_eq!(erc721.token_uri(1), Ok(special));
+
+            assert_eq!(erc721.clear_token_uri(1), Ok(()));
+            assert_eq!(
+                erc721.token_uri(1),
+                Ok(String::from("https://example.com/1"))
+            );
+        }
+
+        #[test]
+        fn pick_weighted_works() {
+            let entries = vec![
+                (String::from("common"), 3),
+                (String::from("never"), 0),
+                (String::from("rare"), 1),
+            ];
+            assert_eq!(pick_weighted(&[0; 32], 0, &[(String::from("a"), 0)]), None);
+
+            // 同じシードなら同じ値、重み0は選ばれない。割合はおおよそ重みの通り
+            let mut counts = [0u32; 3];
+            for i in 0..400u32 {
+                let mut seed = [0u8; 32];
+                seed[..4].copy_from_slice(&i.to_le_bytes());
+                let picked = pick_weighted(&seed, 0, &entries).unwrap();
+                assert_eq!(pick_weighted(&seed, 0, &entries), Some(picked));
+                counts[picked] += 1;
+            }
+            assert_eq!(counts[1], 0);
+            assert!(counts[0] > counts[2] * 2);
+        }
+
+        #[ink::test]
+        fn rarity_table_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            let palette = vec![(String::from("warm"), 1), (String::from("cool"), 1)];
+            let shape = vec![(String::from("circle"), 1)];
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(
+                erc721.set_rarity_table(String::from("palette"), palette.clone()),
+                Err(Error::NotOwner)
+            );
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(
+                erc721.set_rarity_table(String::from("palette"), vec![(String::from("x"), 0)]),
+                Err(Error::InvalidParameter)
+            );
+            assert_eq!(
+                erc721.set_rarity_table(String::from("palette"), palette.clone()),
+                Ok(())
+            );
+            assert_eq!(
+                erc721.set_rarity_table(String::from("shape"), shape.clone()),
+                Ok(())
+            );
+            assert_eq!(
+                erc721.rarity_table(),
+                vec![
+                    (String::from("palette"), palette.clone()),
+                    (String::from("shape"), shape)
+                ]
+            );
+
+            // シードから選ばれた値が属性に入る
+            assert!(erc721.mint(None, 1, None).is_ok());
+            let seed = erc721.seed_of(1).unwrap();
+            let expected = &palette[pick_weighted(&seed, 0, &palette).unwrap()].0;
+            assert_eq!(
+                erc721.get_attribute(1, String::from("palette")),
+                Some(expected.clone())
+            );
+            assert_eq!(
+                erc721.get_attribute(1, String::from("shape")),
+                Some(String::from("circle"))
+            );
+
+            // mintが始まったら変えられない
+            assert_eq!(
+                erc721.set_rarity_table(String::from("shape"), Vec::new()),
+                Err(Error::MintAlreadyStarted)
+            );
+        }
+
+        #[ink::test]
+        fn tokens_with_attribute_works() {
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 3, None).is_ok());
+            let key = || String::from("palette");
+            let warm = || String::from("warm");
+            let cool = || String::from("cool");
+
+            for id in 1..=3 {
+                assert_eq!(erc721.set_attribute(id, key(), warm()), Ok(()));
+            }
+            // 同じ値を入れ直しても重複しない
+            assert_eq!(erc721.set_attribute(1, key(), warm()), Ok(()));
+            assert_eq!(
+                erc721.tokens_with_attribute(key(), warm(), 0, 10),
+                Ok(vec![1, 2, 3])
+            );
+            assert_eq!(
+                erc721.tokens_with_attribute(key(), warm(), 1, 1),
+                Ok(vec![2])
+            );
+            assert_eq!(
+                erc721.tokens_with_attribute(key(), warm(), 5, 1),
+                Ok(Vec::<TokenId>::new())
+            );
+            assert_eq!(
+                erc721.tokens_with_attribute(key(), warm(), 0, DEFAULT_MAX_BATCH_SIZE + 1),
+                Err(Error::BatchTooLarge)
+            );
+
+            // 値を変えると付け替わり、空いた位置は最後の要素で埋まる
+            assert_eq!(erc721.set_attribute(1, key(), cool()), Ok(()));
+            assert_eq!(
+                erc721.tokens_with_attribute(key(), warm(), 0, 10),
+                Ok(vec![3, 2])
+            );
+            assert_eq!(
+                erc721.tokens_with_attribute(key(), cool(), 0, 10),
+                Ok(vec![1])
+            );
+
+            assert_eq!(erc721.remove_attribute(3, key()), Ok(()));
+            assert_eq!(
+                erc721.tokens_with_attribute(key(), warm(), 0, 10),
+                Ok(vec![2])
+            );
+
+            // burnすると一覧から消える
+            assert_eq!(erc721.burn(2), Ok(()));
+            assert_eq!(erc721.attribute_token_count(key(), warm()), 0);
+            assert_eq!(erc721.attribute_token_count(key(), cool()), 1);
+        }
+
+        #[ink::test]
+        fn metadata_update_events_work() {
+            let mut erc721 = new_erc721();
+            // mintしていなければまとめての通知は出ない
+            assert_eq!(
+                erc721.set_base_uri(String::from("https://example.org/")),
+                Ok(())
+            );
+            assert!(erc721.mint(None, 3, None).is_ok());
+
+            assert_eq!(
+                erc721.set_attribute(2, String::from("palette"), String::from("warm")),
+                Ok(())
+            );
+            assert_eq!(erc721.remove_attribute(2, String::from("palette")), Ok(()));
+            assert_eq!(
+                erc721.set_token_uri(3, String::from("ipfs://special")),
+                Ok(())
+            );
+            assert_eq!(erc721.set_token_name(1, String::from("Sunset")), Ok(()));
+            assert_eq!(
+                erc721.set_base_uri(String::from("https://example.com/")),
+                Ok(())
+            );
+
+            let updates = ink::env::test::recorded_events()
+                .filter_map(|event| {
+                    match <Event as scale::Decode>::decode(&mut &event.data[..]).unwrap() {
+                        Event::MetadataUpdate(MetadataUpdate { id }) => Some((id, id)),
+                        Event::BatchMetadataUpdate(BatchMetadataUpdate { from_id, to_id }) => {
+                            Some((from_id, to_id))
+                        }
+                        _ => None,
+                    }
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(updates, vec![(2, 2), (2, 2), (3, 3), (1, 1), (1, 3)]);
+        }
+
+        #[ink::test]
+        fn attributes_work() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+            let key = String::from("palette");
+
+            assert_eq!(
+                erc721.set_attribute(2, key.clone(), String::from("warm")),
+                Err(Error::TokenNotFound)
+            );
+            assert_eq!(
+                erc721.set_attribute(1, String::new(), String::from("warm")),
+                Err(Error::AttributeTooLong)
+            );
+            assert_eq!(
+                erc721.set_attribute(1, key.clone(), String::from("warm")),
+                Ok(())
+            );
+            assert_eq!(
+                erc721.set_attribute(1, key.clone(), String::from("cool")),
+                Ok(())
+            );
+            assert_eq!(
+                erc721.get_attribute(1, key.clone()),
+                Some(String::from("cool"))
+            );
+            assert_eq!(erc721.attribute_keys_of(1), vec![key.clone()]);
+            assert_eq!(
+                PSP34Metadata::get_attribute(&erc721, Id::U32(1), b"palette".to_vec()),
+                Some(b"cool".to_vec())
+            );
+
+            // トークンの所有者でもコントラクトのオーナーでなければ書けない
+            assert_eq!(erc721.transfer(accounts.bob, 1, None, None), Ok(()));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(
+                erc721.set_attribute(1, key.clone(), String::from("warm")),
+                Err(Error::NotOwner)
+            );
+            assert_eq!(
+                erc721.remove_attribute(1, key.clone()),
+                Err(Error::NotOwner)
+            );
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(erc721.remove_attribute(1, key.clone()), Ok(()));
+            assert_eq!(erc721.get_attribute(1, key.clone()), None);
+            for i in 0..MAX_ATTRIBUTES {
+                assert_eq!(
+                    erc721.set_attribute(1, format!("shape{i}"), String::from("1")),
+                    Ok(())
+                );
+            }
+            assert_eq!(
+                erc721.set_attribute(1, key.clone(), String::from("warm")),
+                Err(Error::TooManyAttributes)
+            );
+
+            // burnすると属性も消える
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.burn(1), Ok(()));
+            assert_eq!(erc721.get_attribute(1, String::from("shape0")), None);
+            assert_eq!(erc721.attribute_keys_of(1), Vec::<String>::new());
+        }
+
+        #[ink::test]
+        fn token_metadata_json_works() {
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.token_metadata_json(2), Err(Error::TokenNotFound));
+            // imageがなければシードのSVGになる
+            let svg = erc721.token_svg(1).unwrap();
+            let mut expected = String::from(r#"{"name":"p5js #1","image":"#);
+            push_json_str(
+                &mut expected,
+                &format!("data:image/svg+xml;utf8,{}", svg.replace('#', "%23")),
+            );
+            expected.push_str(r#","attributes":[]}"#);
+            assert_eq!(erc721.token_metadata_json(1), Ok(expected));
+
+            for (key, value) in [
+                ("palette", "warm"),
+                ("description", "a \"generative\" sketch\n"),
+                ("image", "ipfs://image/1.png"),
+                ("shapes", "12"),
+            ] {
+                assert_eq!(
+                    erc721.set_attribute(1, String::from(key), String::from(value)),
+                    Ok(())
+                );
+            }
+            assert_eq!(
+                erc721.token_metadata_json(1),
+                Ok(String::from(concat!(
+                    r#"{"name":"p5js #1","description":"a \"generative\" sketch\n","#,
+                    r#""image":"ipfs://image/1.png","attributes":["#,
+                    r#"{"trait_type":"palette","value":"warm"},"#,
+                    r#"{"trait_type":"shapes","value":"12"}]}"#
+                )))
+            );
+
+            // 所有者が付けた名前と説明が優先される
+            assert_eq!(erc721.set_token_name(1, String::from("Sunset")), Ok(()));
+            assert_eq!(
+                erc721.set_token_description(1, String::from("mine")),
+                Ok(())
+            );
+            assert_eq!(
+                erc721.token_metadata_json(1),
+                Ok(String::from(concat!(
+                    r#"{"name":"Sunset","description":"mine","#,
+                    r#""image":"ipfs://image/1.png","attributes":["#,
+                    r#"{"trait_type":"palette","value":"warm"},"#,
+                    r#"{"trait_type":"shapes","value":"12"}]}"#
+                )))
+            );
+        }
+
+        #[ink::test]
+        fn external_url_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+            let url = String::from("https://example.com/view/1");
+
+            assert_eq!(
+                erc721.set_external_url(2, url.clone()),
+                Err(Error::TokenNotFound)
+            );
+            assert_eq!(
+                erc721.set_external_url(1, "a".repeat(MAX_URL_LEN + 1)),
+                Err(Error::TextTooLong)
+            );
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(
+                erc721.set_external_url(1, url.clone()),
+                Err(Error::NotOwner)
+            );
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(erc721.set_external_url(1, url.clone()), Ok(()));
+            assert_eq!(erc721.external_url(1), Some(url.clone()));
+            assert!(erc721
+                .token_metadata_json(1)
+                .unwrap()
+                .contains(&format!(r#""external_url":"{url}""#)));
+
+            assert_eq!(erc721.set_external_url(1, String::new()), Ok(()));
+            assert_eq!(erc721.external_url(1), None);
+            assert_eq!(erc721.freeze_token_metadata(1), Ok(()));
+            assert_eq!(erc721.set_external_url(1, url), Err(Error::MetadataFrozen));
+        }
+
+        #[ink::test]
+        fn animation_url_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 2, None).is_ok());
+            assert_eq!(erc721.animation_url(1), None);
+
+            // 属性のanimation_urlは既定値も個別の設定もないときだけ使う
+            assert_eq!(
+                erc721.set_attribute(1, String::from("animation_url"), String::from("ipfs://old")),
+                Ok(())
+            );
+            assert!(erc721
+                .token_metadata_json(1)
+                .unwrap()
+                .contains(r#""animation_url":"ipfs://old""#));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(
+                erc721.set_animation_base_url(String::from("https://example.com/live/")),
+                Err(Error::NotOwner)
+            );
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(
+                erc721.set_animation_base_url(String::from("https://example.com/live/")),
+                Ok(())
+            );
+            assert_eq!(
+                erc721.set_animation_url(2, String::from("ipfs://special.html")),
+                Ok(())
+            );
+            assert_eq!(
+                erc721.animation_url(1),
+                Some(String::from("https://example.com/live/1"))
+            );
+            assert_eq!(
+                erc721.animation_url(2),
+                Some(String::from("ipfs://special.html"))
+            );
+            assert_eq!(erc721.animation_url(3), None);
+
+            let json = erc721.token_metadata_json(1).unwrap();
+            assert!(json.contains(r#""animation_url":"https://example.com/live/1""#));
+            assert_eq!(json.matches("animation_url").count(), 1);
+
+            // 個別の設定を消すと既定値に戻る
+            assert_eq!(erc721.set_animation_url(2, String::new()), Ok(()));
+            assert_eq!(
+                erc721.animation_url(2),
+                Some(String::from("https://example.com/live/2"))
+            );
+        }
+
+        #[ink::test]
+        fn with_config_works() {
+            register_mock_random();
+            let config = MetadataConfig {
+                name: String::from("p5js"),
+                symbol: String::from("P5"),
+                base_uri: String::from("https://example.com/"),
+                uses_json_suffix: true,
+                reveal_mode: RevealMode::Immediate,
+                max_supply: MAX_SUPPLY,
+            };
+            let invalid = MetadataConfig {
+                name: String::new(),
+                ..config.clone()
+            };
+            assert_eq!(
+                Erc721::with_config(invalid, 0, 0, 1, 1).map(|_| ()),
+                Err(Error::InvalidParameter)
+            );
+
+            // 最初からリビール済みで、URIに.jsonが付く
+            let mut erc721 = Erc721::with_config(config.clone(), 0, 0, 1, 1).unwrap();
+            assert!(erc721.is_revealed());
+            assert_eq!(erc721.metadata_config(), config);
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(
+                erc721.token_uri(1),
+                Ok(String::from("https://example.com/1.json"))
+            );
+            assert_eq!(erc721.reveal(None, false), Err(Error::AlreadyRevealed));
+        }
+
+        #[ink::test]
+        fn with_config_delayed_reveal_works() {
+            register_mock_random();
+            // revealするまではプレースホルダーを返す
+            let config = MetadataConfig {
+                name: String::from("p5js"),
+                symbol: String::from("P5"),
+                base_uri: String::from("https://example.com/"),
+                uses_json_suffix: true,
+                reveal_mode: RevealMode::Delayed {
+                    placeholder_uri: String::from("ipfs://hidden.json"),
+                },
+                max_supply: MAX_SUPPLY,
+            };
+            let mut erc721 = Erc721::with_config(config.clone(), 0, 0, 1, 1).unwrap();
+            assert!(!erc721.is_revealed());
+            assert_eq!(erc721.metadata_config(), config);
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.token_uri(1), Ok(String::from("ipfs://hidden.json")));
+            assert_eq!(erc721.reveal(None, false), Ok(()));
+            assert_eq!(
+                erc721.token_uri(1),
+                Ok(String::from("https://example.com/1.json"))
+            );
+            assert_eq!(erc721.metadata_config().reveal_mode, RevealMode::Immediate);
+        }
+
+        #[ink::test]
+        fn script_hash_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            let sketch = b"function setup(){createCanvas(400,400);}".to_vec();
+            let mut hash = [0u8; 32];
+            ink::env::hash_bytes::<ink::env::hash::Sha2x256>(&sketch, &mut hash);
+
+            // 未設定なら何も一致しない
+            assert_eq!(erc721.script_hash(), None);
+            assert!(!erc721.verify_script(sketch.clone()));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.set_script_hash(hash), Err(Error::NotOwner));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(erc721.set_script_hash(hash), Ok(()));
+            assert_eq!(erc721.script_hash(), Some(hash));
+            assert!(erc721.verify_script(sketch.clone()));
+            assert!(!erc721.verify_script(b"function setup(){}".to_vec()));
+
+            // 全体を固定したあとは変えられない
+            assert_eq!(erc721.freeze_all_metadata(1, 1), Ok(None));
+            assert_eq!(erc721.set_script_hash([0; 32]), Err(Error::MetadataFrozen));
+            assert!(erc721.verify_script(sketch));
+        }
+
+        #[ink::test]
+        fn mint_entropy_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert_eq!(
+                erc721.mint(None, 1, Some(vec![0; MAX_ENTROPY_LEN + 1])),
+                Err(Error::InvalidParameter)
+            );
+            assert_eq!(erc721.mint(None, 1, None), Ok(vec![1]));
+            assert_eq!(erc721.mint(None, 1, Some(b"hello".to_vec())), Ok(vec![2]));
+
+            assert_eq!(
+                erc721.seed_source(2),
+                Some((accounts.alice, 0, b"hello".to_vec()))
+            );
+            assert_eq!(erc721.seed_source(1), Some((accounts.alice, 0, Vec::new())));
+            assert_eq!(erc721.seed_source(3), None);
+        }
+
+        #[ink::test]
+        fn palette_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            // パレットがなければ選ばれない
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.palette_of(1), None);
+            assert!(erc721.token_html(1).unwrap().contains("const palette=[];"));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(
+                erc721.add_palette(String::from("mono"), vec![[0, 0, 0]]),
+                Err(Error::NotOwner)
+            );
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(
+                erc721.add_palette(String::new(), vec![[0, 0, 0]]),
+                Err(Error::InvalidParameter)
+            );
+            assert_eq!(
+                erc721.add_palette("a".repeat(MAX_PALETTE_NAME_LEN + 1), vec![[0, 0, 0]]),
+                Err(Error::TextTooLong)
+            );
+            assert_eq!(
+                erc721.add_palette(String::from("empty"), Vec::new()),
+                Err(Error::InvalidParameter)
+            );
+            assert_eq!(
+                erc721.add_palette(
+                    String::from("many"),
+                    vec![[0, 0, 0]; MAX_PALETTE_COLORS + 1]
+                ),
+                Err(Error::InvalidParameter)
+            );
+            assert_eq!(
+                erc721.add_palette(
+                    String::from("sunset"),
+                    vec![[0xff, 0x80, 0x00], [0x20, 0x10, 0x40]]
+                ),
+                Ok(0)
+            );
+            assert_eq!(erc721.palette_count(), 1);
+            assert_eq!(
+                erc721.palette(0),
+                Some((
+                    String::from("sunset"),
+                    vec![[0xff, 0x80, 0x00], [0x20, 0x10, 0x40]]
+                ))
+            );
+            assert_eq!(erc721.palette(1), None);
+
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.palette_of(2), Some(0));
+            assert!(erc721
+                .token_html(2)
+                .unwrap()
+                .contains(r##"const palette=["#ff8000","#201040"];"##));
+
+            assert_eq!(
+                erc721.add_palette(String::from("mono"), vec![[0, 0, 0]]),
+                Ok(1)
+            );
+            assert!(erc721.mint(None, 1, None).is_ok());
+            let seed = erc721.seed_of(3).unwrap();
+            assert_eq!(erc721.palette_of(3), pick_palette(&seed, 2));
+        }
+
+        #[ink::test]
+        fn reroll_seed_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert_eq!(
+                erc721.set_rarity_table(
+                    String::from("color"),
+                    vec![(String::from("red"), 1), (String::from("blue"), 1)]
+                ),
+                Ok(())
+            );
+            assert!(erc721.mint(None, 1, None).is_ok());
+            let before = erc721.seed_of(1).unwrap();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.set_reroll_price(10), Err(Error::NotOwner));
+            assert_eq!(erc721.reroll_seed(1), Err(Error::NotOwner));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(erc721.set_reroll_price(10), Ok(()));
+            assert_eq!(erc721.reroll_seed(1), Err(Error::InsufficientPayment));
+
+            ink::env::test::set_value_transferred::<ink::env::DefaultEnvironment>(10);
+            let after = erc721.reroll_seed(1).unwrap();
+            assert_ne!(after, before);
+            assert_eq!(erc721.seed_of(1), Some(after));
+            assert!(erc721.is_rerolled(1));
+            // 属性は新しいシードで選び直される
+            let expected = ["red", "blue"][pick_weighted(
+                &after,
+                0,
+                &[(String::from("red"), 1), (String::from("blue"), 1)],
+            )
+            .unwrap()];
+            assert_eq!(
+                erc721.get_attribute(1, String::from("color")),
+                Some(String::from(expected))
+            );
+
+            let rerolled = ink::env::test::recorded_events().find_map(|event| {
+                match <Event as scale::Decode>::decode(&mut &event.data[..]).unwrap() {
+                    Event::SeedRerolled(rerolled) => Some(rerolled),
+                    _ => None,
+                }
+            });
+            assert_eq!(rerolled.expect("SeedRerolled event").seed, after);
+
+            // 二回目はできない
+            assert_eq!(erc721.reroll_seed(1), Err(Error::AlreadyRerolled));
+        }
+
+        #[ink::test]
+        fn sign_token_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.artist(), None);
+            assert_eq!(erc721.sign_token(1, vec![1; 64]), Err(Error::NotArtist));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.set_artist(Some(accounts.bob)), Err(Error::NotOwner));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(erc721.set_artist(Some(accounts.bob)), Ok(()));
+            assert_eq!(erc721.artist(), Some(accounts.bob));
+
+            // アーティスト以外は署名できない
+            assert_eq!(erc721.sign_token(1, vec![1; 64]), Err(Error::NotArtist));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.sign_token(2, vec![1; 64]), Err(Error::TokenNotFound));
+            assert_eq!(
+                erc721.sign_token(1, Vec::new()),
+                Err(Error::InvalidSignature)
+            );
+            assert_eq!(
+                erc721.sign_token(1, vec![1; MAX_SIGNATURE_LEN + 1]),
+                Err(Error::InvalidSignature)
+            );
+            assert_eq!(erc721.sign_token(1, vec![1; 64]), Ok(()));
+            assert_eq!(erc721.artist_signature(1), Some(vec![1; 64]));
+
+            let signed = ink::env::test::recorded_events().find_map(|event| {
+                match <Event as scale::Decode>::decode(&mut &event.data[..]).unwrap() {
+                    Event::TokenSigned(signed) => Some(signed),
+                    _ => None,
+                }
+            });
+            let signed = signed.expect("TokenSigned event");
+            assert_eq!(signed.id, 1);
+            assert_eq!(signed.artist, accounts.bob);
+
+            // アーティストを解除すると署名できなくなるが、署名は残る
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(erc721.set_artist(None), Ok(()));
+            assert_eq!(erc721.artist(), None);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.sign_token(1, vec![2; 64]), Err(Error::NotArtist));
+            assert_eq!(erc721.artist_signature(1), Some(vec![1; 64]));
+        }
+
+        #[ink::test]
+        fn token_name_and_description_work() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            assert_eq!(
+                erc721.set_token_name(2, String::from("a")),
+                Err(Error::TokenNotFound)
+            );
+            assert_eq!(
+                erc721.set_token_name(1, "a".repeat(MAX_TOKEN_NAME_LEN + 1)),
+                Err(Error::TextTooLong)
+            );
+            assert_eq!(
+                erc721.set_token_description(1, "a".repeat(MAX_TOKEN_DESCRIPTION_LEN + 1)),
+                Err(Error::TextTooLong)
+            );
+            assert_eq!(erc721.set_token_name(1, String::from("Sunset")), Ok(()));
+            assert_eq!(
+                erc721.set_token_description(1, String::from("mine")),
+                Ok(())
+            );
+
+            // 転送しても残り、新しい所有者だけが書き換えられる
+            assert_eq!(erc721.transfer(accounts.bob, 1, None, None), Ok(()));
+            assert_eq!(erc721.token_name(1), Some(String::from("Sunset")));
+            assert_eq!(
+                erc721.set_token_name(1, String::new()),
+                Err(Error::NotOwner)
+            );
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.set_token_name(1, String::new()), Ok(()));
+            assert_eq!(erc721.token_name(1), None);
+
+            assert_eq!(erc721.burn(1), Ok(()));
+            assert_eq!(erc721.token_description(1), None);
+        }
+
+        #[test]
+        fn json_escape_works() {
+            let mut out = String::new();
+            push_json_str(&mut out, "a\"b\\c\u{1}</");
+            assert_eq!(out, r#""a\"b\\c\u0001\u003c/""#);
+        }
+
+        #[test]
+        fn html_escape_works() {
+            let mut out = String::new();
+            push_html_text(&mut out, "a</title><script>\"&");
+            assert_eq!(out, "a&lt;/title&gt;&lt;script&gt;&quot;&amp;");
+        }
+
+        #[ink::test]
+        fn script_chunks_work() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(
+                erc721.upload_script_chunk(0, b"a".to_vec()),
+                Err(Error::NotOwner)
+            );
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(
+                erc721.upload_script_chunk(0, vec![0; MAX_SCRIPT_CHUNK_LEN + 1]),
+                Err(Error::ScriptChunkTooLarge)
+            );
+            // 間を空けてはアップロードできない
+            assert_eq!(
+                erc721.upload_script_chunk(1, b"b".to_vec()),
+                Err(Error::InvalidParameter)
+            );
+
+            // "あ"(3バイト)がチャンクの境目で切れても連結すれば戻る
+            let text = "let a='あ';".as_bytes();
+            assert_eq!(erc721.upload_script_chunk(0, text[..8].to_vec()), Ok(()));
+            assert_eq!(erc721.upload_script_chunk(1, b"x".to_vec()), Ok(()));
+            assert_eq!(erc721.upload_script_chunk(1, text[8..].to_vec()), Ok(()));
+            assert_eq!(erc721.script_size(), 2);
+            assert_eq!(erc721.script_chunk(0), Some(text[..8].to_vec()));
+            assert_eq!(erc721.script(), "let a='あ';");
+
+            assert_eq!(erc721.clear_script(), Ok(()));
+            assert_eq!(erc721.script_size(), 0);
+            assert_eq!(erc721.script_chunk(0), None);
+        }
+
+        #[test]
+        fn render_uri_template_works() {
+            let mut seed = [0u8; 32];
+            seed[0] = 0xab;
+            let hex = format!("ab{}", "0".repeat(62));
+            assert_eq!(
+                render_uri_template("https://host/{id}/meta?seed={seed}", "7", &seed),
+                format!("https://host/7/meta?seed={hex}")
+            );
+            assert_eq!(
+                render_uri_template("{id}{id}", "12", &seed),
+                String::from("1212")
+            );
+            // 知らない置き換えや閉じていない括弧はそのまま
+            assert_eq!(
+                render_uri_template("https://host/{name}/{id", "1", &seed),
+                String::from("https://host/{name}/{id")
+            );
+            assert_eq!(render_uri_template("", "1", &seed), String::new());
+        }
+
+        #[ink::test]
+        fn mint_edition_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.mint_edition(7, 3), Err(Error::NotOwner));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(erc721.mint_edition(7, 0), Err(Error::InvalidParameter));
+            assert_eq!(erc721.mint_edition(7, 3), Ok(vec![2, 3, 4]));
+            assert_eq!(erc721.mint_edition(7, 1), Err(Error::EditionExists));
+            assert_eq!(erc721.edition(7), Some((2, 3)));
+            assert_eq!(erc721.edition(8), None);
+            assert_eq!(erc721.owner_of(4), Some(accounts.alice));
+
+            assert_eq!(erc721.edition_of(1), None);
+            assert_eq!(erc721.edition_of(2), Some((7, 1)));
+            assert_eq!(erc721.edition_of(4), Some((7, 3)));
+            assert_eq!(
+                erc721.token_uri(1),
+                Ok(String::from("https://example.com/1"))
+            );
+            assert_eq!(
+                erc721.token_uri(3),
+                Ok(String::from("https://example.com/7/2"))
+            );
+
+            let minted = ink::env::test::recorded_events().find_map(|event| {
+                match <Event as scale::Decode>::decode(&mut &event.data[..]).unwrap() {
+                    Event::EditionMinted(minted) => Some(minted),
+                    _ => None,
+                }
+            });
+            let minted = minted.expect("EditionMinted event");
+            assert_eq!((minted.edition_id, minted.first_id, minted.size), (7, 2, 3));
+
+            // burnするとエディションの情報も消える
+            assert_eq!(erc721.burn(3), Ok(()));
+            assert_eq!(erc721.edition_of(3), None);
+            assert_eq!(erc721.edition(7), Some((2, 3)));
+        }
+
+        #[ink::test]
+        fn uri_template_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 2, None).is_ok());
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(
+                erc721.set_uri_template(String::from("https://host/{id}")),
+                Err(Error::NotOwner)
+            );
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(
+                erc721.set_uri_template(String::from("https://host/{id}/meta?seed={seed}")),
+                Ok(())
+            );
+            let mut seed = String::new();
+            push_hex(&mut seed, &erc721.seed_of(2).unwrap());
+            assert_eq!(
+                erc721.token_uri(2),
+                Ok(format!("https://host/2/meta?seed={seed}"))
+            );
+
+            // 個別のURIはテンプレートより優先する
+            assert_eq!(
+                erc721.set_token_uri(1, String::from("ipfs://pinned.json")),
+                Ok(())
+            );
+            assert_eq!(erc721.token_uri(1), Ok(String::from("ipfs://pinned.json")));
+
+            // 空文字でベースURIに戻る
+            assert_eq!(erc721.set_uri_template(String::new()), Ok(()));
+            assert_eq!(
+                erc721.token_uri(2),
+                Ok(String::from("https://example.com/2"))
+            );
+        }
+
+        #[test]
+        fn seed_svg_works() {
+            let mut seed = [0u8; 32];
+            seed[..3].copy_from_slice(&[0x12, 0xab, 0xff]);
+            seed[3] = 0;
+            seed[4..8].copy_from_slice(&[10, 20, 70, 0x40]);
+            let svg = seed_svg(&seed);
+            assert!(svg.starts_with("<svg "));
+            assert!(svg.contains("<rect width='256' height='256' fill='#12abff'/>"));
+            assert!(
+                svg.contains("<circle cx='10' cy='20' r='22' fill='#144640' fill-opacity='0.8'/>")
+            );
+            assert_eq!(svg.matches("<circle").count(), 3);
+            seed[3] = 3;
+            assert_eq!(seed_svg(&seed).matches("<circle").count(), 6);
+            assert!(svg.ends_with("</svg>"));
+        }
+
+        #[ink::test]
+        fn token_svg_works() {
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 2, None).is_ok());
+            assert_eq!(erc721.token_svg(3), Err(Error::TokenNotFound));
+            assert_eq!(
+                erc721.token_svg(1),
+                Ok(seed_svg(&erc721.seed_of(1).unwrap()))
+            );
+            assert_ne!(erc721.token_svg(1), erc721.token_svg(2));
+        }
+
+        #[ink::test]
+        fn token_html_works() {
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.token_html(2), Err(Error::TokenNotFound));
+
+            let sketch = String::from("function setup(){randomSeed(seed)}");
+            let (head, tail) = sketch.as_bytes().split_at(10);
+            assert_eq!(erc721.upload_script_chunk(0, head.to_vec()), Ok(()));
+            assert_eq!(erc721.upload_script_chunk(1, tail.to_vec()), Ok(()));
+            assert_eq!(
+                erc721.set_attribute(1, String::from("palette"), String::from("warm")),
+                Ok(())
+            );
+
+            let mut seed = String::new();
+            push_hex(&mut seed, &erc721.seed_of(1).unwrap());
+            let seed = format!("0x{seed}");
+            let html = erc721.token_html(1).unwrap();
+            assert!(html.starts_with("<!DOCTYPE html>"));
+            assert!(html.contains("<title>p5js #1</title>"));
+            assert!(html.contains(&format!(
+                r#"const tokenId="1";const seed="{seed}";const attributes={{"palette":"warm"}};"#
+            )));
+            assert!(html.contains(&format!("<script>{sketch}</script>")));
+            assert!(html.ends_with("</html>"));
+        }
+
+        #[cfg(not(feature = "randomness"))]
+        #[ink::test]
+        fn seeds_work() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 2, None).is_ok());
+            assert!(erc721.mint(None, 1, Some(b"hello".to_vec())).is_ok());
+
+            let mut expected = [0u8; 32];
+            ink::env::hash_encoded::<ink::env::hash::Blake2x256, _>(
+                &(accounts.alice, 0u32, 1 as TokenId, &b""[..]),
+                &mut expected,
+            );
+            assert_eq!(erc721.seed_of(1), Some(expected));
+            assert_ne!(erc721.seed_of(2), erc721.seed_of(1));
+            assert_eq!(erc721.seed_of(4), None);
+
+            // 保存された材料からシードを計算し直せる
+            let (caller, block, entropy) = erc721.seed_source(3).unwrap();
+            let mut seed = [0u8; 32];
+            ink::env::hash_encoded::<ink::env::hash::Blake2x256, _>(
+                &(caller, block, 3 as TokenId, &entropy[..]),
+                &mut seed,
+            );
+            assert_eq!(erc721.seed_of(3), Some(seed));
+
+            // 転送しても変わらず、burnすると消える
+            assert_eq!(erc721.transfer(accounts.bob, 1, None, None), Ok(()));
+            assert_eq!(erc721.seed_of(1), Some(expected));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.burn(1), Ok(()));
+            assert_eq!(erc721.seed_of(1), None);
+        }
+
+        // ノードの代わりにsubjectを反転して返すエクステンション
+        #[cfg(feature = "randomness")]
+        struct MockRandom {
+            fail: bool,
+        }
+
+        #[cfg(feature = "randomness")]
+        impl ink::env::test::ChainExtension for MockRandom {
+            fn func_id(&self) -> u32 {
+                1101
+            }
+
+            fn call(&mut self, input: &[u8], output: &mut Vec<u8>) -> u32 {
+                if self.fail {
+                    return 1;
+                }
+                // 入力はVec<u8>としてエンコードされて渡ってくる
+                let input = <Vec<u8> as scale::Decode>::decode(&mut &input[..]).unwrap();
+                let subject = <[u8; 32] as scale::Decode>::decode(&mut &input[..]).unwrap();
+                scale::Encode::encode_to(&subject.map(|b| !b), output);
+                0
+            }
+        }
+
+        #[cfg(feature = "randomness")]
+        #[ink::test]
+        fn random_seeds_work() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+
+            ink::env::test::register_chain_extension(MockRandom { fail: true });
+            assert_eq!(
+                erc721.mint(None, 1, None),
+                Err(Error::RandomnessUnavailable)
+            );
+            assert_eq!(erc721.total_supply(), 0);
+
+            ink::env::test::register_chain_extension(MockRandom { fail: false });
+            assert!(erc721.mint(None, 1, None).is_ok());
+            let mut subject = [0u8; 32];
+            ink::env::hash_encoded::<ink::env::hash::Blake2x256, _>(
+                &(accounts.alice, 0u32, 1 as TokenId, &b""[..]),
+                &mut subject,
+            );
+            assert_eq!(erc721.seed_of(1), Some(subject.map(|b| !b)));
+        }
+
+        #[ink::test]
+        fn deadline_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(100);
+            assert_eq!(
+                erc721.approve(accounts.bob, 1, Some(99)),
+                Err(Error::DeadlineExpired)
+            );
+            assert_eq!(
+                erc721.transfer(accounts.bob, 1, None, Some(99)),
+                Err(Error::DeadlineExpired)
+            );
+            assert_eq!(
+                erc721.transfer_from(accounts.alice, accounts.bob, 1, None, Some(99)),
+                Err(Error::DeadlineExpired)
+            );
+            assert_eq!(erc721.owner_of(1), Some(accounts.alice));
+
+            // 期限ちょうどまでは有効
+            assert_eq!(erc721.transfer(accounts.bob, 1, None, Some(100)), Ok(()));
+        }
+
+        #[ink::test]
+        fn dry_run_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            let zero = AccountId::from(internal::ZERO_ACCOUNT);
+
+            assert_eq!(erc721.can_mint(accounts.alice, 1), Ok(()));
+            assert_eq!(
+                erc721.can_mint(accounts.alice, 0),
+                Err(Error::InvalidParameter)
+            );
+            assert_eq!(erc721.can_mint(zero, 1), Err(Error::ZeroAddressRecipient));
+            assert_eq!(
+                erc721.can_mint(accounts.alice, DEFAULT_MAX_BATCH_SIZE + 1),
+                Err(Error::BatchTooLarge)
+            );
+
+            assert_eq!(
+                erc721.can_transfer(accounts.alice, accounts.bob, 1),
+                Err(Error::TokenNotFound)
+            );
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.can_transfer(accounts.alice, accounts.bob, 1), Ok(()));
+            assert_eq!(
+                erc721.can_transfer(accounts.alice, accounts.alice, 1),
+                Err(Error::TransferToSelf)
+            );
+            assert_eq!(
+                erc721.can_transfer(accounts.bob, accounts.charlie, 1),
+                Err(Error::NotOwner)
+            );
+
+            // 状態は変わらない
+            assert_eq!(erc721.owner_of(1), Some(accounts.alice));
+            assert_eq!(erc721.total_supply(), 1);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(
+                erc721.can_transfer(accounts.alice, accounts.bob, 1),
+                Err(Error::NotApproved)
+            );
+        }
+
+        #[ink::test]
+        fn safe_transfer_from_with_data_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            // コントラクトでなければフックは呼ばない
+            assert_eq!(
+                erc721.safe_transfer_from_with_data(accounts.alice, accounts.bob, 1, vec![1, 2, 3]),
+                Ok(())
+            );
+            assert_eq!(erc721.owner_of(1), Some(accounts.bob));
+            assert_eq!(
+                erc721.safe_transfer_from_with_data(accounts.alice, accounts.bob, 1, Vec::new()),
+                Err(Error::NotOwner)
+            );
+        }
+
+        #[ink::test]
+        fn supports_interface_works() {
+            let erc721 = new_erc721();
+            assert!(erc721.supports_interface(INTERFACE_ID_ERC165));
+            assert!(erc721.supports_interface(INTERFACE_ID_ERC721));
+            assert!(erc721.supports_interface(INTERFACE_ID_ERC721_METADATA));
+            assert!(erc721.supports_interface(INTERFACE_ID_ERC4906));
+            assert_eq!(
+                erc721.supports_interface(INTERFACE_ID_ERC721_ENUMERABLE),
+                cfg!(feature = "enumerable")
+            );
+            assert!(!erc721.supports_interface([0xff; 4]));
+        }
+
+        #[ink::test]
+        fn psp34_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            assert_eq!(PSP34::balance_of(&erc721, accounts.alice), 1);
+            assert_eq!(PSP34::total_supply(&erc721), 1);
+            assert_eq!(PSP34::owner_of(&erc721, Id::U32(1)), Some(accounts.alice));
+            assert_eq!(PSP34::owner_of(&erc721, Id::U8(1)), Some(accounts.alice));
+            assert_eq!(PSP34::owner_of(&erc721, Id::Bytes(vec![1])), None);
+
+            // 個別の承認と取り消し
+            assert_eq!(
+                PSP34::approve(&mut erc721, accounts.bob, Some(Id::U32(1)), true),
+                Ok(())
+            );
+            assert!(PSP34::allowance(
+                &erc721,
+                accounts.alice,
+                accounts.bob,
+                Some(Id::U32(1))
+            ));
+            assert!(!PSP34::allowance(
+                &erc721,
+                accounts.alice,
+                accounts.bob,
+                None
+            ));
+            assert_eq!(
+                PSP34::approve(&mut erc721, accounts.bob, Some(Id::U32(1)), false),
+                Ok(())
+            );
+            assert!(!PSP34::allowance(
+                &erc721,
+                accounts.alice,
+                accounts.bob,
+                Some(Id::U32(1))
+            ));
+
+            // 全承認
+            assert_eq!(
+                PSP34::approve(&mut erc721, accounts.bob, None, true),
+                Ok(())
+            );
+            assert!(PSP34::allowance(
+                &erc721,
+                accounts.alice,
+                accounts.bob,
+                None
+            ));
+            assert_eq!(
+                PSP34::approve(&mut erc721, accounts.alice, None, true),
+                Err(PSP34Error::SelfApprove)
+            );
+
+            assert_eq!(
+                PSP34::transfer(&mut erc721, accounts.charlie, Id::U32(2), Vec::new()),
+                Err(PSP34Error::TokenNotExists)
+            );
+            assert_eq!(
+                PSP34::transfer(&mut erc721, accounts.charlie, Id::U32(1), Vec::new()),
+                Ok(())
+            );
+            assert_eq!(erc721.owner_of(1), Some(accounts.charlie));
+            assert_eq!(
+                PSP34::transfer(&mut erc721, accounts.bob, Id::U32(1), Vec::new()),
+                Err(PSP34Error::NotApproved)
+            );
+
+            let collection = PSP34::collection_id(&erc721);
+            assert_eq!(
+                PSP34Metadata::get_attribute(&erc721, collection.clone(), b"symbol".to_vec()),
+                Some(b"P5".to_vec())
+            );
+            assert_eq!(
+                PSP34Metadata::get_attribute(&erc721, collection, b"color".to_vec()),
+                None
+            );
+            assert_eq!(
+                PSP34Metadata::get_attribute(&erc721, Id::U32(1), b"name".to_vec()),
+                None
+            );
+        }
+
+        #[cfg(feature = "enumerable")]
+        #[ink::test]
+        fn psp34_enumerable_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            let missing = Err(PSP34Error::TokenNotExists);
+            assert_eq!(PSP34Enumerable::token_by_index(&erc721, 0), missing);
+            assert!(erc721.mint(None, 3, None).is_ok());
+            assert_eq!(erc721.transfer(accounts.bob, 1, None, None), Ok(()));
+
+            assert_eq!(PSP34Enumerable::token_by_index(&erc721, 2), Ok(Id::U128(3)));
+            assert_eq!(PSP34Enumerable::token_by_index(&erc721, 3), missing);
+            assert_eq!(PSP34Enumerable::token_by_index(&erc721, u128::MAX), missing);
+
+            assert_eq!(
+                PSP34Enumerable::owners_token_by_index(&erc721, accounts.bob, 0),
+                Ok(Id::U128(1))
+            );
+            assert_eq!(
+                PSP34Enumerable::owners_token_by_index(&erc721, accounts.alice, 0),
+                Ok(Id::U128(3))
+            );
+            assert_eq!(
+                PSP34Enumerable::owners_token_by_index(&erc721, accounts.alice, 2),
+                missing
+            );
+            assert_eq!(
+                PSP34Enumerable::owners_token_by_index(&erc721, accounts.charlie, 0),
+                missing
+            );
+        }
+
+        #[cfg(not(feature = "enumerable"))]
+        #[ink::test]
+        fn psp34_enumerable_requires_feature() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(
+                PSP34Enumerable::token_by_index(&erc721, 0),
+                Err(PSP34Error::TokenNotExists)
+            );
+            assert_eq!(
+                PSP34Enumerable::owners_token_by_index(&erc721, accounts.alice, 0),
+                Err(PSP34Error::TokenNotExists)
+            );
+        }
+
+        #[test]
+        fn psp34_selectors_match() {
+            assert_eq!(
+                ink::selector_bytes!("PSP34::transfer"),
+                PSP34_TRANSFER_SELECTOR
+            );
+        }
+
+        #[ink::test]
+        fn reapprove_and_revoke_work() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            // 上書きできる
+            assert_eq!(erc721.approve(accounts.bob, 1, None), Ok(()));
+            assert_eq!(erc721.approve(accounts.charlie, 1, None), Ok(()));
+            assert_eq!(erc721.get_approved(1), Ok(Some(accounts.charlie)));
+
+            // 所有者以外は取り消せない
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.charlie);
+            assert_eq!(erc721.revoke_approval(1), Err(Error::NotApproved));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(erc721.revoke_approval(1), Ok(()));
+            assert_eq!(erc721.get_approved(1), Ok(None));
+            assert_eq!(erc721.revoke_approval(2), Err(Error::TokenNotFound));
+
+            let events = ink::env::test::recorded_events().collect::<Vec<_>>();
+            let decoded = <Event as scale::Decode>::decode(&mut &events[3].data[..]).unwrap();
+            match decoded {
+                Event::Approval(Approval { to, id, .. }) => {
+                    assert_eq!(to, AccountId::from(internal::ZERO_ACCOUNT));
+                    assert_eq!(id, 1);
+                }
+                _ => panic!("expected Approval"),
+            }
+        }
+
+        #[cfg(feature = "enumerable")]
+        #[ink::test]
+        fn token_by_index_works() {
+            let mut erc721 = new_erc721();
+            assert_eq!(erc721.token_by_index(0), None);
+            for _ in 0..3 {
+                assert!(erc721.mint(None, 1, None).is_ok());
+            }
+            assert_eq!(erc721.token_by_index(0), Some(1));
+            assert_eq!(erc721.token_by_index(2), Some(3));
+
+            // 最後の要素が空いた位置に移る
+            assert_eq!(erc721.burn(1), Ok(()));
+            assert_eq!(erc721.token_by_index(0), Some(3));
+            assert_eq!(erc721.token_by_index(1), Some(2));
+            assert_eq!(erc721.token_by_index(2), None);
+        }
+
+        #[cfg(feature = "enumerable")]
+        #[ink::test]
+        fn token_of_owner_by_index_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            for _ in 0..3 {
+                assert!(erc721.mint(None, 1, None).is_ok());
+            }
+            assert_eq!(erc721.token_of_owner_by_index(accounts.alice, 0), Some(1));
+
+            // 転送すると最後の要素が空いた位置に移る
+            assert_eq!(erc721.transfer(accounts.bob, 1, None, None), Ok(()));
+            assert_eq!(erc721.token_of_owner_by_index(accounts.alice, 0), Some(3));
+            assert_eq!(erc721.token_of_owner_by_index(accounts.alice, 1), Some(2));
+            assert_eq!(erc721.token_of_owner_by_index(accounts.alice, 2), None);
+            assert_eq!(erc721.token_of_owner_by_index(accounts.bob, 0), Some(1));
+
+            assert_eq!(erc721.burn(2), Ok(()));
+            assert_eq!(erc721.token_of_owner_by_index(accounts.alice, 0), Some(3));
+            assert_eq!(erc721.token_of_owner_by_index(accounts.alice, 1), None);
+        }
+
+        #[cfg(feature = "enumerable")]
+        #[ink::test]
+        fn tokens_of_owner_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            for _ in 0..5 {
+                assert!(erc721.mint(None, 1, None).is_ok());
+            }
+
+            assert_eq!(erc721.tokens_of_owner(accounts.alice, 0, 2), Ok(vec![1, 2]));
+            assert_eq!(erc721.tokens_of_owner(accounts.alice, 2, 2), Ok(vec![3, 4]));
+            assert_eq!(erc721.tokens_of_owner(accounts.alice, 4, 2), Ok(vec![5]));
+            assert_eq!(
+                erc721.tokens_of_owner(accounts.alice, 10, 2),
+                Ok(Vec::<TokenId>::new())
+            );
+            assert_eq!(
+                erc721.tokens_of_owner(accounts.bob, 0, 2),
+                Ok(Vec::<TokenId>::new())
+            );
+
+            // limitがmax_batch_sizeを超えたら切り詰めずにエラーにする
+            assert_eq!(erc721.set_max_batch_size(3), Ok(()));
+            assert_eq!(
+                erc721.tokens_of_owner(accounts.alice, 0, 4),
+                Err(Error::BatchTooLarge)
+            );
+            assert_eq!(
+                erc721.tokens_of_owner(accounts.alice, 0, 3),
+                Ok(vec![1, 2, 3])
+            );
+        }
+
+        #[ink::test]
+        fn exists_works() {
+            let mut erc721 = new_erc721();
+            assert!(!erc721.exists(1));
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert!(erc721.exists(1));
+            assert_eq!(erc721.burn(1), Ok(()));
+            assert!(!erc721.exists(1));
+        }
+
+        #[ink::test]
+        fn get_approved_distinguishes_missing_token() {
+            let mut erc721 = new_erc721();
+            assert_eq!(erc721.get_approved(1), Err(Error::TokenNotFound));
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.get_approved(1), Ok(None));
+        }
+
+        #[ink::test]
+        fn checked_owner_of_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert_eq!(erc721.checked_owner_of(1), Err(Error::TokenNotFound));
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.checked_owner_of(1), Ok(accounts.alice));
+        }
+
+        // 上限5で作るのでfixed-supplyでは作れない
+        #[cfg(not(feature = "fixed-supply"))]
+        #[ink::test]
+        fn mint_many_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            register_mock_random();
+            let mut erc721 = Erc721::new(
+                String::from("p5js"),
+                String::from("P5"),
+                String::from("https://example.com/"),
+                5,
+                10,
+                0,
+                1,
+                2,
+            )
+            .unwrap();
+
+            // n個分ちょうど払う
+            ink::env::test::set_value_transferred::<ink::env::DefaultEnvironment>(20);
+            assert_eq!(erc721.mint_many(3), Err(Error::InsufficientPayment));
+            assert_eq!(erc721.mint_many(0), Err(Error::InvalidParameter));
+            assert_eq!(erc721.mint_many(2), Ok(vec![1, 3]));
+            assert_eq!(erc721.balance_of(accounts.alice), 2);
+            assert_eq!(erc721.holder_count(), 1);
+            assert_eq!(erc721.minter_of(3), Some(accounts.alice));
+            assert_eq!(ink::env::test::recorded_events().count(), 2);
+
+            // オーナーだけが代金なしで配れる
+            ink::env::test::set_value_transferred::<ink::env::DefaultEnvironment>(0);
+            assert_eq!(
+                erc721.mint_many_to(accounts.bob, 4),
+                Err(Error::MaxSupplyReached)
+            );
+            assert_eq!(erc721.mint_many_to(accounts.bob, 3), Ok(vec![5, 7, 9]));
+            assert_eq!(erc721.balance_of(accounts.bob), 3);
+            assert_eq!(erc721.total_supply(), 5);
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.mint_many_to(accounts.bob, 1), Err(Error::NotOwner));
+        }
+
+        #[ink::test]
+        fn mint_to_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert_eq!(
+                erc721.mint_to(AccountId::from(internal::ZERO_ACCOUNT)),
+                Err(Error::ZeroAddressRecipient)
+            );
+            assert_eq!(erc721.mint_to(accounts.bob), Ok(1));
+            assert_eq!(erc721.owner_of(1), Some(accounts.bob));
+            assert_eq!(erc721.minter_of(1), Some(accounts.bob));
+            assert_eq!(erc721.balance_of(accounts.alice), 0);
+        }
+
+        #[ink::test]
+        fn mint_with_recipient_and_quantity_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert_eq!(erc721.mint(Some(accounts.bob), 2, None), Ok(vec![1, 2]));
+            assert_eq!(erc721.mint(None, 1, None), Ok(vec![3]));
+            assert_eq!(erc721.balance_of(accounts.bob), 2);
+            assert_eq!(erc721.balance_of(accounts.alice), 1);
+            assert_eq!(erc721.mint(None, 0, None), Err(Error::InvalidParameter));
+        }
+
+        #[ink::test]
+        fn burn_batch_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 3, None).is_ok());
+            assert!(erc721.mint(Some(accounts.bob), 2, None).is_ok());
+            assert_eq!(erc721.set_max_batch_size(4), Ok(()));
+
+            // 一つでも失敗すれば何も消えない
+            assert_eq!(erc721.burn_batch(vec![1, 4]), Err(Error::NotApproved));
+            assert_eq!(erc721.burn_batch(vec![1, 1]), Err(Error::InvalidParameter));
+            assert_eq!(erc721.burn_batch(vec![1, 9]), Err(Error::TokenNotFound));
+            assert_eq!(
+                erc721.burn_batch(vec![1, 2, 3, 4, 5]),
+                Err(Error::BatchTooLarge)
+            );
+            assert_eq!(erc721.total_supply(), 5);
+
+            // オペレーターなら複数の所有者のトークンをまとめてburnできる
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.set_approval_for_all(accounts.charlie, true), Ok(()));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(erc721.set_approval_for_all(accounts.charlie, true), Ok(()));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.charlie);
+            assert_eq!(erc721.burn_batch(vec![1, 4, 3]), Ok(()));
+
+            assert_eq!(erc721.balance_of(accounts.alice), 1);
+            assert_eq!(erc721.balance_of(accounts.bob), 1);
+            assert_eq!(erc721.total_supply(), 2);
+            assert_eq!(erc721.total_burned(), 3);
+            assert!(!erc721.exists(3));
+            #[cfg(feature = "enumerable")]
+            {
+                assert_eq!(erc721.tokens_of_owner(accounts.alice, 0, 4), Ok(vec![2]));
+                assert_eq!(erc721.tokens_of_owner(accounts.bob, 0, 4), Ok(vec![5]));
+            }
+        }
+
+        #[ink::test]
+        fn balance_counters_are_checked() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            // 所有数が0なのにトークンを持っている壊れた状態でもtrapしない
+            erc721.erc721.owned_tokens_count.insert(accounts.alice, &0);
+            assert_eq!(
+                erc721.transfer(accounts.bob, 1, None, None),
+                Err(Error::Underflow)
+            );
+
+            let full = u64::MAX;
+            erc721.erc721.owned_tokens_count.insert(accounts.bob, &full);
+            erc721.erc721.owned_tokens_count.insert(accounts.alice, &1);
+            assert_eq!(
+                erc721.transfer(accounts.bob, 1, None, None),
+                Err(Error::Overflow)
+            );
+            assert_eq!(
+                erc721.mint(Some(accounts.bob), 1, None),
+                Err(Error::Overflow)
+            );
+
+            // オフチェーンではエラーでも所有者の削除が戻らないので最後に確認する
+            erc721.erc721.owned_tokens_count.insert(accounts.alice, &0);
+            assert_eq!(erc721.burn(1), Err(Error::Underflow));
+        }
+
+        #[ink::test]
+        fn fund_works() {
+            let mut erc721 = new_erc721();
+            assert_eq!(erc721.fund(), Err(Error::InsufficientPayment));
+            ink::env::test::set_value_transferred::<ink::env::DefaultEnvironment>(100);
+            assert_eq!(erc721.fund(), Ok(()));
+            assert_eq!(ink::env::test::recorded_events().count(), 1);
+        }
+
+        #[ink::test]
+        fn tip_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert_eq!(erc721.tip(1), Err(Error::TokenNotFound));
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.tip(1), Err(Error::InsufficientPayment));
+
+            // bobからアーティスト(alice)に投げ銭
+            let contract = ink::env::account_id::<ink::env::DefaultEnvironment>();
+            ink::env::test::set_account_balance::<ink::env::DefaultEnvironment>(contract, 100);
+            let before =
+                ink::env::test::get_account_balance::<ink::env::DefaultEnvironment>(accounts.alice)
+                    .unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            ink::env::test::set_value_transferred::<ink::env::DefaultEnvironment>(30);
+            assert_eq!(erc721.tip(1), Ok(()));
+            assert_eq!(
+                ink::env::test::get_account_balance::<ink::env::DefaultEnvironment>(accounts.alice),
+                Ok(before + 30)
+            );
+
+            // アーティストを設定したらアーティストに届く
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(erc721.set_artist(Some(accounts.charlie)), Ok(()));
+            let before = ink::env::test::get_account_balance::<ink::env::DefaultEnvironment>(
+                accounts.charlie,
+            )
+            .unwrap();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            ink::env::test::set_value_transferred::<ink::env::DefaultEnvironment>(20);
+            assert_eq!(erc721.tip(1), Ok(()));
+            assert_eq!(
+                ink::env::test::get_account_balance::<ink::env::DefaultEnvironment>(
+                    accounts.charlie
+                ),
+                Ok(before + 20)
+            );
+        }
+
+        #[ink::test]
+        fn sweep_requires_owner() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+
+            // クロスコントラクト呼び出しはオフチェーンで動かないので権限チェックだけ確認する
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(
+                erc721.sweep_psp22(accounts.django, accounts.bob, 10),
+                Err(Error::NotOwner)
+            );
+            assert_eq!(
+                erc721.sweep_psp34(accounts.django, Id::U32(1), accounts.bob),
+                Err(Error::NotOwner)
+            );
+        }
+
+        #[test]
+        fn foreign_transfer_result_works() {
+            // 呼び出し先が返したバイト列を実際の戻り値の型で読む
+            fn decode<E: scale::Encode + scale::Decode>(
+                output: ink::MessageResult<Result<(), E>>,
+            ) -> Result<(), Error> {
+                let bytes = scale::Encode::encode(&output);
+                let decoded =
+                    <ink::MessageResult<Result<(), E>> as scale::Decode>::decode(&mut &bytes[..])
+                        .unwrap();
+                foreign_transfer_result(Ok(decoded))
+            }
+
+            assert_eq!(decode::<PSP22Error>(Ok(Ok(()))), Ok(()));
+            // 呼び出し先が拒否したら失敗にする
+            assert_eq!(
+                decode::<PSP22Error>(Ok(Err(PSP22Error::InsufficientBalance))),
+                Err(Error::TransferFailed)
+            );
+            assert_eq!(
+                decode::<PSP34Error>(Ok(Err(PSP34Error::NotApproved))),
+                Err(Error::TransferFailed)
+            );
+            assert_eq!(
+                decode::<PSP34Error>(Err(ink::LangError::CouldNotReadInput)),
+                Err(Error::TransferFailed)
+            );
+            assert_eq!(
+                foreign_transfer_result::<PSP22Error>(Err(ink::env::Error::CalleeTrapped)),
+                Err(Error::TransferFailed)
+            );
+
+            // 戻り値を()として読むと拒否されたことに気づけない
+            let rejected: ink::MessageResult<Result<(), PSP22Error>> =
+                Ok(Err(PSP22Error::InsufficientBalance));
+            let bytes = scale::Encode::encode(&rejected);
+            assert_eq!(
+                <ink::MessageResult<()> as scale::Decode>::decode(&mut &bytes[..]),
+                Ok(Ok(()))
+            );
+        }
+
+        #[ink::test]
+        fn balance_is_u64() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+
+            // u32を超える所有数でも扱える
+            let large = u64::from(u32::MAX) + 1;
+            erc721
+                .erc721
+                .owned_tokens_count
+                .insert(accounts.alice, &large);
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.balance_of(accounts.alice), large + 1);
+        }
+
+        #[ink::test]
+        fn transfer_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            assert_eq!(erc721.transfer(accounts.bob, 1, None, None), Ok(()));
+            assert_eq!(erc721.owner_of(1), Some(accounts.bob));
+            assert_eq!(erc721.balance_of(accounts.alice), 0);
+            assert_eq!(erc721.balance_of(accounts.bob), 1);
+        }
+
+        #[ink::test]
+        fn exists_range_works() {
+            let mut erc721 = new_erc721();
+            for _ in 0..3 {
+                assert!(erc721.mint(None, 1, None).is_ok());
+            }
+            assert_eq!(erc721.burn(2), Ok(()));
+
+            assert_eq!(
+                erc721.exists_range(0, 5),
+                Ok(vec![false, true, false, true, false])
+            );
+            assert_eq!(
+                erc721.exists_range(0, DEFAULT_MAX_BATCH_SIZE + 1),
+                Err(Error::BatchTooLarge)
+            );
+        }
+
+        #[ink::test]
+        fn transfer_count_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.transfer_count(1), 0);
+
+            assert_eq!(erc721.transfer(accounts.bob, 1, None, None), Ok(()));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.transfer(accounts.alice, 1, None, None), Ok(()));
+            assert_eq!(erc721.transfer_count(1), 2);
+        }
+
+        #[ink::test]
+        fn minter_of_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert_eq!(erc721.minter_of(1), None);
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            // 転送しても最初のmint者は変わらない
+            assert_eq!(erc721.transfer(accounts.bob, 1, None, None), Ok(()));
+            assert_eq!(erc721.minter_of(1), Some(accounts.alice));
+        }
+
+        #[cfg(feature = "royalties")]
+        #[ink::test]
+        fn royalty_info_splits_with_minter() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            // ロイヤリティは5%
+            assert_eq!(
+                erc721.royalty_info(1, 10_000),
+                Ok(vec![(accounts.alice, 500)])
+            );
+
+            // ロイヤリティの20%をmint者に
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.alice);
+            assert_eq!(erc721.set_minter_royalty_share(2_000), Ok(()));
+            assert_eq!(
+                erc721.royalty_info(1, 10_000),
+                Ok(vec![(accounts.alice, 400), (accounts.bob, 100)])
+            );
+            assert_eq!(erc721.royalty_info(2, 10_000), Err(Error::TokenNotFound));
+
+            // アーティストを設定したらオーナーの代わりに受け取る
+            assert_eq!(erc721.set_artist(Some(accounts.charlie)), Ok(()));
+            assert_eq!(
+                erc721.royalty_info(1, 10_000),
+                Ok(vec![(accounts.charlie, 400), (accounts.bob, 100)])
+            );
+        }
+
+        #[ink::test]
+        fn timestamps_work() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(1_000);
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.minted_at(1), Some(1_000));
+            assert_eq!(erc721.last_transfer_at(1), Some(1_000));
+
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(5_000);
+            assert_eq!(erc721.transfer(accounts.bob, 1, None, None), Ok(()));
+            assert_eq!(erc721.minted_at(1), Some(1_000));
+            assert_eq!(erc721.last_transfer_at(1), Some(5_000));
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.burn(1), Ok(()));
+            assert_eq!(erc721.minted_at(1), None);
+        }
+
+        #[ink::test]
+        fn transfer_with_memo_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            assert_eq!(
+                erc721.transfer(accounts.bob, 1, Some("a".repeat(MAX_MEMO_LEN + 1)), None),
+                Err(Error::MemoTooLong)
+            );
+            assert_eq!(
+                erc721.transfer(accounts.bob, 1, Some(String::from("happy birthday")), None),
+                Ok(())
+            );
+
+            // mintとtransferの2つ
+            let events = ink::env::test::recorded_events().collect::<Vec<_>>();
+            assert_eq!(events.len(), 2);
+            let decoded = <Event as scale::Decode>::decode(&mut &events[1].data[..]).unwrap();
+            match decoded {
+                Event::Transfer(Transfer { memo, .. }) => {
+                    assert_eq!(memo, Some(String::from("happy birthday")))
+                }
+                _ => panic!("unexpected event"),
+            }
+        }
+
+        #[ink::test]
+        fn transfer_from_wrong_owner_fails() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            // fromが所有者ではない
+            assert_eq!(
+                erc721.transfer_from(accounts.bob, accounts.charlie, 1, None, None),
+                Err(Error::NotOwner)
+            );
+            assert_eq!(erc721.owner_of(1), Some(accounts.alice));
+        }
+
+        #[ink::test]
+        fn approve_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            assert_eq!(erc721.approve(accounts.bob, 1, None), Ok(()));
+            assert_eq!(erc721.get_approved(1), Ok(Some(accounts.bob)));
+
+            // Approveされたアカウントはtransferできる
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(
+                erc721.transfer_from(accounts.alice, accounts.charlie, 1, None, None),
+                Ok(())
+            );
+            assert_eq!(erc721.owner_of(1), Some(accounts.charlie));
+            assert_eq!(erc721.get_approved(1), Ok(None));
+        }
+
+        #[ink::test]
+        fn is_approved_or_owner_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(!erc721.is_approved_or_owner(accounts.alice, 1));
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            assert!(erc721.is_approved_or_owner(accounts.alice, 1));
+            assert!(!erc721.is_approved_or_owner(accounts.bob, 1));
+            assert_eq!(erc721.approve(accounts.bob, 1, None), Ok(()));
+            assert!(erc721.is_approved_or_owner(accounts.bob, 1));
+            assert_eq!(erc721.set_approval_for_all(accounts.charlie, true), Ok(()));
+            assert!(erc721.is_approved_or_owner(accounts.charlie, 1));
+        }
+
+        #[ink::test]
+        fn granular_errors_work() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+            let zero = AccountId::from(internal::ZERO_ACCOUNT);
+
+            assert_eq!(
+                erc721.transfer(zero, 1, None, None),
+                Err(Error::ZeroAddressRecipient)
+            );
+            assert_eq!(
+                erc721.approve(zero, 1, None),
+                Err(Error::ZeroAddressRecipient)
+            );
+            assert_eq!(
+                erc721.set_approval_for_all(accounts.alice, true),
+                Err(Error::SelfApproval)
+            );
+            assert_eq!(
+                erc721.approve(accounts.alice, 1, None),
+                Err(Error::ApproveToOwner)
+            );
+            assert_eq!(
+                erc721.transfer(accounts.alice, 1, None, None),
+                Err(Error::TransferToSelf)
+            );
+        }
+
+        #[ink::test]
+        fn approve_missing_token_fails() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+
+            // 以前はexpectでパニックしていた
+            assert_eq!(
+                erc721.approve(accounts.bob, 1, None),
+                Err(Error::TokenNotFound)
+            );
+            assert_eq!(
+                erc721.transfer_from(accounts.alice, accounts.bob, 1, None, None),
+                Err(Error::TokenNotFound)
+            );
+        }
+
+        #[ink::test]
+        fn transfer_by_unapproved_fails() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(
+                erc721.transfer_from(accounts.alice, accounts.bob, 1, None, None),
+                Err(Error::NotApproved)
+            );
+            assert_eq!(
+                erc721.approve(accounts.bob, 1, None),
+                Err(Error::NotApproved)
+            );
+        }
+
+        #[ink::test]
+        fn operators_of_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert_eq!(erc721.operators_of(accounts.alice), vec![]);
+
+            assert_eq!(erc721.set_approval_for_all(accounts.bob, true), Ok(()));
+            assert_eq!(erc721.set_approval_for_all(accounts.charlie, true), Ok(()));
+            // 2回承認しても重複しない
+            assert_eq!(erc721.set_approval_for_all(accounts.bob, true), Ok(()));
+            assert_eq!(
+                erc721.operators_of(accounts.alice),
+                vec![accounts.bob, accounts.charlie]
+            );
+
+            assert_eq!(erc721.set_approval_for_all(accounts.bob, false), Ok(()));
+            assert_eq!(erc721.operators_of(accounts.alice), vec![accounts.charlie]);
+            assert!(!erc721.is_approved_for_all(accounts.alice, accounts.bob));
+        }
+
+        #[ink::test]
+        fn burn_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            assert_eq!(erc721.burn(1), Ok(()));
+            assert_eq!(erc721.owner_of(1), None);
+            assert_eq!(erc721.balance_of(accounts.alice), 0);
+            assert_eq!(erc721.burn(1), Err(Error::TokenNotFound));
+
+            // mintはfrom、burnはtoがNoneになる
+            let transfers = ink::env::test::recorded_events()
+                .filter_map(|event| {
+                    match <Event as scale::Decode>::decode(&mut &event.data[..]).unwrap() {
+                        Event::Transfer(Transfer { from, to, .. }) => Some((from, to)),
+                        _ => None,
+                    }
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                transfers,
+                vec![(None, Some(accounts.alice)), (Some(accounts.alice), None)]
+            );
+        }
+
+        #[ink::test]
+        fn holder_count_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert_eq!(erc721.holder_count(), 0);
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.holder_count(), 1);
+
+            assert_eq!(erc721.transfer(accounts.bob, 1, None, None), Ok(()));
+            assert_eq!(erc721.holder_count(), 2);
+            assert_eq!(erc721.transfer(accounts.bob, 2, None, None), Ok(()));
+            assert_eq!(erc721.holder_count(), 1);
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.burn(1), Ok(()));
+            assert_eq!(erc721.burn(2), Ok(()));
+            assert_eq!(erc721.holder_count(), 0);
+        }
+
+        #[ink::test]
+        fn top_holders_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            for _ in 0..3 {
+                assert!(erc721.mint(None, 1, None).is_ok());
+            }
+            assert_eq!(erc721.top_holders(), vec![(accounts.alice, 3)]);
+
+            assert_eq!(erc721.transfer(accounts.bob, 1, None, None), Ok(()));
+            assert_eq!(
+                erc721.top_holders(),
+                vec![(accounts.alice, 2), (accounts.bob, 1)]
+            );
+            assert_eq!(erc721.transfer(accounts.bob, 2, None, None), Ok(()));
+            assert_eq!(erc721.transfer(accounts.bob, 3, None, None), Ok(()));
+            assert_eq!(erc721.top_holders(), vec![(accounts.bob, 3)]);
+        }
+
+        #[ink::test]
+        fn total_burned_works() {
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.total_supply(), 2);
+
+            assert_eq!(erc721.burn(1), Ok(()));
+            assert_eq!(erc721.total_burned(), 1);
+            assert_eq!(erc721.total_supply(), 1);
+        }
+
+        #[ink::test]
+        fn burn_clears_token_data() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.approve(accounts.bob, 1, None), Ok(()));
+
+            assert_eq!(erc721.burn(1), Ok(()));
+            assert_eq!(erc721.get_approved(1), Err(Error::TokenNotFound));
+            // get_approvedはトークンがないとエラーになるのでストレージを直接見る
+            assert_eq!(erc721.erc721.token_approvals.get(1), None);
+
+            // Approveされたアカウントがburnしても消える
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.approve(accounts.bob, 2, None), Ok(()));
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.burn_batch(vec![2]), Ok(()));
+            assert_eq!(erc721.erc721.token_approvals.get(2), None);
+        }
+
+        #[ink::test]
+        fn burn_by_non_owner_fails() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.burn(1), Err(Error::NotApproved));
+            assert_eq!(erc721.owner_of(1), Some(accounts.alice));
+        }
+
+        #[ink::test]
+        fn burn_by_approved_works() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 2, None).is_ok());
+            assert_eq!(erc721.approve(accounts.bob, 1, None), Ok(()));
+            assert_eq!(erc721.set_approval_for_all(accounts.charlie, true), Ok(()));
+
+            // 個別にApproveされたアカウント
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(erc721.burn(2), Err(Error::NotApproved));
+            assert_eq!(erc721.burn(1), Ok(()));
+
+            // オペレーター
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts.charlie);
+            assert_eq!(erc721.burn(2), Ok(()));
+            assert_eq!(erc721.balance_of(accounts.alice), 0);
+
+            let events = ink::env::test::recorded_events().collect::<Vec<_>>();
+            let decoded =
+                <Event as scale::Decode>::decode(&mut &events.last().unwrap().data[..]).unwrap();
+            match decoded {
+                Event::Burned(Burned {
+                    id,
+                    owner,
+                    operator,
+                }) => {
+                    assert_eq!(id, 2);
+                    assert_eq!(owner, accounts.alice);
+                    assert_eq!(operator, accounts.charlie);
+                }
+                _ => panic!("expected Burned"),
+            }
+        }
+
+        #[ink::test]
+        fn check_invariants_detects_stale_approval() {
+            let accounts = ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let mut erc721 = new_erc721();
+            assert!(erc721.mint(None, 1, None).is_ok());
+            assert_eq!(erc721.approve(accounts.bob, 1, None), Ok(()));
+            assert_eq!(erc721.check_invariants(1, 10), Ok(()));
+
+            // 存在しないトークンにApprovalを残す
+            erc721.erc721.token_approvals.insert(5, &accounts.bob);
+            assert_eq!(
+                erc721.check_invariants(1, 10),
+                Err(Error::InvariantViolation)
+            );
+            assert_eq!(erc721.check_invariants(1, 4), Ok(()));
+        }
+
+        // テスト用の簡単な疑似乱数(xorshift)
+        struct Rng(u64);
+
+        impl Rng {
+            fn next(&mut self) -> u64 {
+                self.0 ^= self.0 << 13;
+                self.0 ^= self.0 >> 7;
+                self.0 ^= self.0 << 17;
+                self.0
+            }
+
+            fn below(&mut self, n: u64) -> u64 {
+                self.next() % n
+            }
+        }
+
+        // 所有数の合計 == 存在するトークン数、所有者と所有数が一致しているか
+        fn assert_invariants(erc721: &Erc721, accounts: &[AccountId]) {
+            let mut live = 0;
+            for account in accounts {
+                let owned = (1..erc721.token_id)
+                    .filter(|id| erc721.owner_of(*id) == Some(*account))
+                    .count() as u64;
+                assert_eq!(erc721.balance_of(*account), owned);
+                live += owned;
+                // 所有者ごとの一覧が所有しているトークンと一致する
+                #[cfg(feature = "enumerable")]
+                {
+                    let mut listed = (0..owned)
+                        .map(|i| erc721.token_of_owner_by_index(*account, i).unwrap())
+                        .collect::<Vec<_>>();
+                    assert_eq!(erc721.token_of_owner_by_index(*account, owned), None);
+                    listed.sort();
+                    let ids = (1..erc721.token_id)
+                        .filter(|id| erc721.owner_of(*id) == Some(*account))
+                        .collect::<Vec<_>>();
+                    assert_eq!(listed, ids);
+                }
+            }
+            let total: u64 = accounts.iter().map(|a| erc721.balance_of(*a)).sum();
+            assert_eq!(total, live);
+            assert_eq!(total, erc721.total_supply());
+            let holders = accounts
+                .iter()
+                .filter(|a| erc721.balance_of(**a) > 0)
+                .count() as u64;
+            assert_eq!(erc721.holder_count(), holders);
+            // 全トークン一覧に存在するトークンがちょうど一回ずつ並んでいる
+            #[cfg(feature = "enumerable")]
+            {
+                let mut listed = (0..total)
+                    .map(|i| erc721.token_by_index(i).unwrap())
+                    .collect::<Vec<_>>();
+                assert_eq!(erc721.token_by_index(total), None);
+                listed.sort();
+                let live_ids = (1..erc721.token_id)
+                    .filter(|id| erc721.owner_of(*id).is_some())
+                    .collect::<Vec<_>>();
+                assert_eq!(listed, live_ids);
+            }
+            for id in 1..erc721.token_id {
+                // 存在しないトークンにApprovalが残っていない
+                if erc721.owner_of(id).is_none() {
+                    assert_eq!(erc721.erc721.token_approvals.get(id), None);
+                }
+            }
+        }
+
+        // ランダムな操作列を流して不変条件が崩れないか確認する
+        #[ink::test]
+        fn random_operations_keep_invariants() {
+            let default_accounts =
+                ink::env::test::default_accounts::<ink::env::DefaultEnvironment>();
+            let accounts = [
+                default_accounts.alice,
+                default_accounts.bob,
+                default_accounts.charlie,
+                default_accounts.django,
+            ];
+
+            for seed in 1..=10u64 {
+                // シードごとに別のコントラクトとしてストレージを分ける
+                ink::env::test::set_callee::<ink::env::DefaultEnvironment>(AccountId::from(
+                    [seed as u8; 32],
+                ));
+                ink::env::test::set_caller::<ink::env::DefaultEnvironment>(accounts[0]);
+                let mut erc721 = new_erc721();
+                let mut rng = Rng(seed);
+
+                for _ in 0..200 {
+                    let caller = accounts[rng.below(4) as usize];
+                    let other = accounts[rng.below(4) as usize];
+                    let third = accounts[rng.below(4) as usize];
+                    let id = rng.below(erc721.token_id as u64 + 1) as TokenId;
+                    let third_id = rng.below(erc721.token_id as u64 + 1) as TokenId;
+                    ink::env::test::set_caller::<ink::env::DefaultEnvironment>(caller);
+
+                    // エラーになる操作も含めて流す
+                    let _ = match rng.below(8) {
+                        0 => erc721.mint(None, 1, None).map(|_| ()),
+                        5 => erc721.mint_many(rng.below(3) as u32 + 1).map(|_| ()),
+                        6 => erc721.burn_batch(vec![id, third_id]),
+                        1 => erc721.transfer(other, id, None, None),
+                        2 => erc721.approve(other, id, None),
+                        3 => erc721.set_approval_for_all(other, rng.below(2) == 0),
+                        4 => erc721.transfer_from(other, third, id, None, None),
+                        _ => erc721.burn(id),
+                    };
+
+                    assert_invariants(&erc721, &accounts);
+                }
+                assert_eq!(erc721.check_invariants(1, 50), Ok(()));
+                assert_eq!(erc721.check_invariants(51, 50), Ok(()));
+            }
+        }
+
+        #[test]
+        fn format_id_works() {
+            let mut buf = [0u8; MAX_ID_DIGITS];
+            assert_eq!(format_id(0, &mut buf), "0");
+            assert_eq!(format_id(1024, &mut buf), "1024");
+            assert_eq!(
+                format_id(TokenId::MAX, &mut buf),
+                "340282366920938463463374607431768211455"
+            );
         }
     }
 }
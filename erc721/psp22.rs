@@ -0,0 +1,16 @@
+// PSP22(Polkadotのファンジブルトークン標準)のエラー
+// 間違って送られたPSP22トークンを回収するとき、呼び出し先のtransferの戻り値を読むために使う
+use ink::prelude::string::String;
+use scale::{Decode, Encode};
+
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum PSP22Error {
+    // 標準にないエラーはここに入れる
+    Custom(String),
+    InsufficientBalance,
+    InsufficientAllowance,
+    ZeroRecipientAddress,
+    ZeroSenderAddress,
+    SafeTransferCheckFailed(String),
+}
@@ -0,0 +1,81 @@
+// PSP34(PolkadotのNFT標準)のトレイト定義
+// セレクタはトレイト名込み("PSP34::transfer"など)で計算されるので標準のものと一致する
+use ink::prelude::string::String;
+use ink::prelude::vec::Vec;
+use ink::primitives::AccountId;
+use scale::{Decode, Encode};
+
+// PSP34のトークンID
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum Id {
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    U128(u128),
+    Bytes(Vec<u8>),
+}
+
+// PSP34で決められているエラー
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum PSP34Error {
+    // 標準にないエラーはここに入れる
+    Custom(String),
+    SelfApprove,
+    NotApproved,
+    TokenExists,
+    TokenNotExists,
+    SafeTransferCheckFailed(String),
+}
+
+#[ink::trait_definition]
+pub trait PSP34 {
+    // コレクションのID
+    #[ink(message)]
+    fn collection_id(&self) -> Id;
+
+    #[ink(message)]
+    fn balance_of(&self, owner: AccountId) -> u32;
+
+    #[ink(message)]
+    fn owner_of(&self, id: Id) -> Option<AccountId>;
+
+    // idがNoneなら全トークンの承認を見る
+    #[ink(message)]
+    fn allowance(&self, owner: AccountId, operator: AccountId, id: Option<Id>) -> bool;
+
+    // idがNoneなら全トークンの承認を変更する
+    #[ink(message)]
+    fn approve(
+        &mut self,
+        operator: AccountId,
+        id: Option<Id>,
+        approved: bool,
+    ) -> Result<(), PSP34Error>;
+
+    #[ink(message)]
+    fn transfer(&mut self, to: AccountId, id: Id, data: Vec<u8>) -> Result<(), PSP34Error>;
+
+    #[ink(message)]
+    fn total_supply(&self) -> u128;
+}
+
+#[ink::trait_definition]
+pub trait PSP34Metadata {
+    #[ink(message)]
+    fn get_attribute(&self, id: Id, key: Vec<u8>) -> Option<Vec<u8>>;
+}
+
+// PSP34::enumerable。burnで順番は入れ替わる
+#[ink::trait_definition]
+pub trait PSP34Enumerable {
+    // 所有者のindex番目のトークン(0 <= index < balance_of(owner))
+    #[ink(message)]
+    fn owners_token_by_index(&self, owner: AccountId, index: u128) -> Result<Id, PSP34Error>;
+
+    // index番目のトークン(0 <= index < total_supply)
+    #[ink(message)]
+    fn token_by_index(&self, index: u128) -> Result<Id, PSP34Error>;
+}
@@ -0,0 +1,47 @@
+// 乱数を返すチェーンエクステンション(randomnessフィーチャー)
+// ノード側にfunc_id 1101で乱数を返すエクステンションが必要
+// mintする人がブロック番号などからシードを予想して狙ったものを引けないようにする
+use ink::env::{DefaultEnvironment, Environment};
+use scale::{Decode, Encode};
+
+#[ink::chain_extension]
+pub trait FetchRandom {
+    type ErrorCode = RandomReadErr;
+
+    // subjectごとに違う乱数を返す
+    #[ink(extension = 1101)]
+    fn fetch_random(subject: [u8; 32]) -> [u8; 32];
+}
+
+// ノードから乱数を取れなかった
+#[derive(Encode, Decode, Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum RandomReadErr {
+    FailGetRandomSource,
+}
+
+impl ink::env::chain_extension::FromStatusCode for RandomReadErr {
+    fn from_status_code(status_code: u32) -> Result<(), Self> {
+        match status_code {
+            0 => Ok(()),
+            _ => Err(Self::FailGetRandomSource),
+        }
+    }
+}
+
+// 型はDefaultEnvironmentと同じで、チェーンエクステンションだけ追加した環境
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum Erc721Environment {}
+
+impl Environment for Erc721Environment {
+    const MAX_EVENT_TOPICS: usize = <DefaultEnvironment as Environment>::MAX_EVENT_TOPICS;
+
+    type AccountId = <DefaultEnvironment as Environment>::AccountId;
+    type Balance = <DefaultEnvironment as Environment>::Balance;
+    type Hash = <DefaultEnvironment as Environment>::Hash;
+    type Timestamp = <DefaultEnvironment as Environment>::Timestamp;
+    type BlockNumber = <DefaultEnvironment as Environment>::BlockNumber;
+
+    type ChainExtension = FetchRandom;
+}
@@ -0,0 +1,23 @@
+// トークンを受け取るコントラクトが実装するトレイト
+// safe_transfer_from_with_dataはtoがコントラクトならon_erc721_receivedを呼ぶ
+use ink::prelude::vec::Vec;
+use ink::primitives::AccountId;
+
+use crate::internal::TokenId;
+
+// 受け取るときにon_erc721_receivedが返す値(このメッセージのセレクタ)
+pub const ON_ERC721_RECEIVED_SELECTOR: [u8; 4] =
+    ink::selector_bytes!("Erc721Receiver::on_erc721_received");
+
+#[ink::trait_definition]
+pub trait Erc721Receiver {
+    // 受け取るならON_ERC721_RECEIVED_SELECTORを返す。それ以外を返すと転送は失敗する
+    #[ink(message)]
+    fn on_erc721_received(
+        &mut self,
+        operator: AccountId,
+        from: AccountId,
+        id: TokenId,
+        data: Vec<u8>,
+    ) -> [u8; 4];
+}